@@ -0,0 +1,92 @@
+//! Runs the Allan variance analysis over a recorded log.
+//!
+//! Usage: `cargo run --example allan_variance -- <log.csv> <odr_hz> [scale]`
+//!
+//! Every line of the log holds one sample as three comma separated values
+//! (x, y, z). Lines that do not parse, such as a header, are skipped. Raw
+//! register values can be converted by passing a scale, e.g. `1/131` for
+//! gyroscope samples recorded at ±250º/s.
+use std::{env, fs, process};
+
+use icm20608g::analysis::{noise_parameters, AllanPoint, AllanVariance};
+use icm20608g::structs::Vec3;
+
+fn parse_scale(value: &str) -> Option<f32> {
+    match value.split_once('/') {
+        Some((numerator, denominator)) => {
+            Some(numerator.trim().parse::<f32>().ok()? / denominator.trim().parse::<f32>().ok()?)
+        }
+        None => value.trim().parse().ok(),
+    }
+}
+
+fn parse_sample(line: &str) -> Option<Vec3<f32>> {
+    let mut values = line.split(',').map(|value| value.trim().parse::<f32>());
+    let sample = Vec3 {
+        x: values.next()?.ok()?,
+        y: values.next()?.ok()?,
+        z: values.next()?.ok()?,
+    };
+    Some(sample)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: {} <log.csv> <odr_hz> [scale]", args[0]);
+        process::exit(2);
+    }
+    let log = fs::read_to_string(&args[1]).unwrap_or_else(|error| {
+        eprintln!("unable to read {}: {error}", args[1]);
+        process::exit(1);
+    });
+    let Ok(sample_rate_hz) = args[2].parse::<f32>() else {
+        eprintln!("invalid output data rate: {}", args[2]);
+        process::exit(2);
+    };
+    let scale = match args.get(3) {
+        Some(value) => parse_scale(value).unwrap_or_else(|| {
+            eprintln!("invalid scale: {value}");
+            process::exit(2);
+        }),
+        None => 1.0,
+    };
+
+    let samples: Vec<Vec3<f32>> = log.lines().filter_map(parse_sample).collect();
+    println!(
+        "{} samples, {:.1} s at {sample_rate_hz} Hz",
+        samples.len(),
+        samples.len() as f32 / sample_rate_hz
+    );
+
+    let mut curve = [AllanPoint::default(); 128];
+    let points = AllanVariance::new(sample_rate_hz)
+        .with_scale(scale)
+        .compute(&samples, &mut curve);
+    let curve = &curve[..points];
+
+    println!("{:>12} {:>14} {:>14} {:>14}", "tau [s]", "x", "y", "z");
+    for point in curve {
+        println!(
+            "{:>12.5} {:>14.6e} {:>14.6e} {:>14.6e}",
+            point.tau, point.deviation.x, point.deviation.y, point.deviation.z
+        );
+    }
+
+    let noise = noise_parameters(curve);
+    println!();
+    println!(
+        "random walk [units/√Hz]: x {:.6e}  y {:.6e}  z {:.6e}",
+        noise.random_walk.x, noise.random_walk.y, noise.random_walk.z
+    );
+    println!(
+        "bias instability [units]: x {:.6e} (tau {:.1} s)  y {:.6e} (tau {:.1} s)  z {:.6e} (tau {:.1} s)",
+        noise.bias_instability.x,
+        noise.bias_instability_tau.x,
+        noise.bias_instability.y,
+        noise.bias_instability_tau.y,
+        noise.bias_instability.z,
+        noise.bias_instability_tau.z
+    );
+}
@@ -0,0 +1,270 @@
+use super::AxisSample;
+use crate::structs::Vec3;
+
+// Allan deviation at slope -1/2 equals the random walk coefficient at tau = 1 s
+const RANDOM_WALK_SLOPE: f32 = -0.5;
+// Ratio between the Allan deviation floor and the bias instability, sqrt(2 ln 2 / pi)
+const BIAS_INSTABILITY_FACTOR: f32 = 0.664;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AllanPoint {
+    // Cluster time in seconds
+    pub tau: f32,
+    // Allan deviation per axis, in the units of the scaled samples
+    pub deviation: Vec3<f32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NoiseParameters {
+    // Angle (gyro, units/s/√Hz) or velocity (accel, units/√Hz) random walk
+    pub random_walk: Vec3<f32>,
+    // Bias instability in the units of the samples
+    pub bias_instability: Vec3<f32>,
+    // Cluster time in seconds at which the bias instability floor was found
+    pub bias_instability_tau: Vec3<f32>,
+}
+
+// Overlapping Allan deviation of a recording taken at a fixed output data rate.
+//
+// Cluster times are spaced logarithmically with `points_per_decade` points per
+// decade, from one sample up to a third of the recording. No buffers are
+// allocated, every cluster time is a single pass over the samples.
+pub struct AllanVariance {
+    sample_rate_hz: f32,
+    scale: f32,
+    points_per_decade: u16,
+}
+impl AllanVariance {
+    #[must_use]
+    pub const fn new(sample_rate_hz: f32) -> Self {
+        Self {
+            sample_rate_hz,
+            scale: 1.0,
+            points_per_decade: 10,
+        }
+    }
+
+    /// Multiplies every sample by `scale` before the analysis, e.g.
+    /// `1.0 / GyroConfig::sensitivity()` to analyse raw gyroscope samples in º/s.
+    #[must_use]
+    pub const fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    #[must_use]
+    pub fn with_points_per_decade(mut self, points_per_decade: u16) -> Self {
+        self.points_per_decade = points_per_decade.max(1);
+        self
+    }
+
+    /// Computes the Allan deviation curve into `out` and returns the number of points written
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn compute<S: AxisSample>(&self, samples: &[S], out: &mut [AllanPoint]) -> usize {
+        let max_cluster = samples.len() / 3;
+        let step = libm::pow(10.0, 1.0 / f64::from(self.points_per_decade));
+        let mut written = 0;
+        let mut previous = 0;
+        let mut exponent = 0;
+        while written < out.len() {
+            let cluster = libm::floor(libm::pow(step, f64::from(exponent))) as usize;
+            exponent += 1;
+            if cluster > max_cluster {
+                break;
+            }
+            if cluster == previous {
+                continue;
+            }
+            previous = cluster;
+            let [x, y, z] = self.deviation(samples, cluster);
+            out[written] = AllanPoint {
+                tau: cluster as f32 / self.sample_rate_hz,
+                deviation: Vec3 { x, y, z },
+            };
+            written += 1;
+        }
+        written
+    }
+
+    // Overlapping Allan deviation for clusters of `m` samples. Two adjacent
+    // cluster sums are slid over the data so no integrated signal has to be stored.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn deviation<S: AxisSample>(&self, samples: &[S], m: usize) -> [f32; 3] {
+        let value = |index: usize| samples[index].axes().map(f64::from);
+        let mut first = [0.0_f64; 3];
+        let mut second = [0.0_f64; 3];
+        for i in 0..m {
+            let (a, b) = (value(i), value(i + m));
+            for axis in 0..3 {
+                first[axis] += a[axis];
+                second[axis] += b[axis];
+            }
+        }
+        let terms = samples.len() - 2 * m + 1;
+        let mut sum = [0.0_f64; 3];
+        for k in 0..terms {
+            if k > 0 {
+                let (leaving, middle, entering) =
+                    (value(k - 1), value(k + m - 1), value(k + 2 * m - 1));
+                for axis in 0..3 {
+                    first[axis] += middle[axis] - leaving[axis];
+                    second[axis] += entering[axis] - middle[axis];
+                }
+            }
+            for axis in 0..3 {
+                let difference = second[axis] - first[axis];
+                sum[axis] += difference * difference;
+            }
+        }
+        let m = m as f64;
+        let scale = f64::from(self.scale);
+        sum.map(|sum| (libm::sqrt(sum / (2.0 * m * m * terms as f64)) * scale) as f32)
+    }
+}
+
+/// Extracts random walk and bias instability from an Allan deviation curve.
+///
+/// The random walk is read off the part of the curve whose slope is closest to
+/// -1/2, extrapolated to tau = 1 s. The bias instability is the floor of the curve
+/// divided by 0.664.
+#[must_use]
+pub fn noise_parameters(curve: &[AllanPoint]) -> NoiseParameters {
+    let axis = |select: fn(&Vec3<f32>) -> f32| {
+        let mut random_walk = 0.0;
+        let mut best_slope_error = f32::INFINITY;
+        for pair in curve.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            let (deviation_a, deviation_b) = (select(&a.deviation), select(&b.deviation));
+            if deviation_a <= 0.0 || deviation_b <= 0.0 {
+                continue;
+            }
+            let slope = libm::log10f(deviation_b / deviation_a) / libm::log10f(b.tau / a.tau);
+            let error = (slope - RANDOM_WALK_SLOPE).abs();
+            if error < best_slope_error {
+                best_slope_error = error;
+                random_walk = f32::midpoint(
+                    deviation_a * libm::sqrtf(a.tau),
+                    deviation_b * libm::sqrtf(b.tau),
+                );
+            }
+        }
+        let floor = curve
+            .iter()
+            .min_by(|a, b| select(&a.deviation).total_cmp(&select(&b.deviation)));
+        let (instability, tau) = floor.map_or((0.0, 0.0), |point| {
+            (
+                select(&point.deviation) / BIAS_INSTABILITY_FACTOR,
+                point.tau,
+            )
+        });
+        (random_walk, instability, tau)
+    };
+    let (walk_x, instability_x, tau_x) = axis(|v| v.x);
+    let (walk_y, instability_y, tau_y) = axis(|v| v.y);
+    let (walk_z, instability_z, tau_z) = axis(|v| v.z);
+    NoiseParameters {
+        random_walk: Vec3 {
+            x: walk_x,
+            y: walk_y,
+            z: walk_z,
+        },
+        bias_instability: Vec3 {
+            x: instability_x,
+            y: instability_y,
+            z: instability_z,
+        },
+        bias_instability_tau: Vec3 {
+            x: tau_x,
+            y: tau_y,
+            z: tau_z,
+        },
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::test_support::Noise;
+
+    #[test]
+    fn white_noise_falls_with_square_root_of_tau() {
+        let mut noise = Noise::new(7);
+        let samples: Vec<Vec3<f32>> = (0..30_000)
+            .map(|_| Vec3::new(0.1 * noise.normal(), 0.2 * noise.normal(), 0.5))
+            .collect();
+        let allan = AllanVariance::new(100.0).with_points_per_decade(5);
+        let mut curve = [AllanPoint::default(); 32];
+        let len = allan.compute(&samples, &mut curve);
+        let curve = &curve[..len];
+        // One sample up to a third of the recording, 5 points per decade
+        assert_eq!(curve[0].tau, 0.01);
+        assert!(curve.last().unwrap().tau <= 100.0);
+        assert!(len > 15);
+        for point in &curve[..10] {
+            let expected = 0.1 / libm::sqrtf(point.tau * 100.0);
+            assert!(
+                (point.deviation.x / expected - 1.0).abs() < 0.1,
+                "{point:?}"
+            );
+            assert!(
+                (point.deviation.y / expected - 2.0).abs() < 0.2,
+                "{point:?}"
+            );
+            assert_eq!(point.deviation.z, 0.0);
+        }
+
+        let parameters = noise_parameters(curve);
+        assert!(
+            (parameters.random_walk.x - 0.01).abs() < 0.001,
+            "{parameters:?}"
+        );
+        assert!(
+            (parameters.random_walk.y - 0.02).abs() < 0.002,
+            "{parameters:?}"
+        );
+    }
+
+    #[test]
+    fn scale_applies_to_raw_samples() {
+        let mut noise = Noise::new(3);
+        let samples: Vec<Vec3<f32>> = (0..3_000)
+            .map(|_| Vec3::new(noise.normal(), 0.0, 0.0))
+            .collect();
+        let mut raw = [AllanPoint::default(); 4];
+        let mut scaled = [AllanPoint::default(); 4];
+        assert_eq!(AllanVariance::new(10.0).compute(&samples, &mut raw), 4);
+        AllanVariance::new(10.0)
+            .with_scale(0.5)
+            .compute(&samples, &mut scaled);
+        for (raw, scaled) in raw.iter().zip(&scaled) {
+            assert!((scaled.deviation.x - raw.deviation.x * 0.5).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn reads_random_walk_and_bias_floor() {
+        let point = |tau: f32, deviation: f32| AllanPoint {
+            tau,
+            deviation: Vec3::new(deviation, deviation, deviation),
+        };
+        let curve = [
+            point(0.1, 0.1 / libm::sqrtf(0.1)),
+            point(1.0, 0.1),
+            point(10.0, 0.05),
+            point(100.0, 0.08),
+        ];
+        let parameters = noise_parameters(&curve);
+        assert!((parameters.random_walk.x - 0.1).abs() < 1e-6);
+        assert!((parameters.bias_instability.x - 0.05 / 0.664).abs() < 1e-6);
+        assert_eq!(parameters.bias_instability_tau.x, 10.0);
+    }
+}
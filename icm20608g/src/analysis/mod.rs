@@ -0,0 +1,27 @@
+use crate::structs::{AccelMeasurements, GyroscopeMeasurements, Vec3};
+
+mod allan;
+mod spectrum;
+pub use allan::{noise_parameters, AllanPoint, AllanVariance, NoiseParameters};
+pub use spectrum::{SpectralPeak, Spectrum, SpectrumAnalyzer, SpectrumSource, Window};
+
+// A three axis sample that can be fed to the analyses. Raw measurements are
+// scaled by the `with_scale` option of the analysis.
+pub trait AxisSample: Copy {
+    fn axes(&self) -> [f32; 3];
+}
+impl AxisSample for Vec3<f32> {
+    fn axes(&self) -> [f32; 3] {
+        [self.x, self.y, self.z]
+    }
+}
+impl AxisSample for GyroscopeMeasurements {
+    fn axes(&self) -> [f32; 3] {
+        [f32::from(self.x), f32::from(self.y), f32::from(self.z)]
+    }
+}
+impl AxisSample for AccelMeasurements {
+    fn axes(&self) -> [f32; 3] {
+        [f32::from(self.x), f32::from(self.y), f32::from(self.z)]
+    }
+}
@@ -0,0 +1,423 @@
+use core::f32::consts::PI;
+
+use super::AxisSample;
+use crate::fifo::FifoFrame;
+use crate::motion::Axis;
+use crate::structs::Vec3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Window {
+    // No window, only for signals periodic in the snapshot
+    Rectangular,
+    // Good general purpose leakage suppression
+    #[default]
+    Hann,
+    Hamming,
+    // Lowest leakage, wider peaks
+    BlackmanHarris,
+    // Accurate peak amplitudes, widest peaks
+    FlatTop,
+}
+impl Window {
+    // Cosine sum coefficients a0 - a1 cos + a2 cos 2x - a3 cos 3x + a4 cos 4x
+    const fn coefficients(self) -> [f32; 5] {
+        match self {
+            Self::Rectangular => [1.0, 0.0, 0.0, 0.0, 0.0],
+            Self::Hann => [0.5, 0.5, 0.0, 0.0, 0.0],
+            Self::Hamming => [0.54, 0.46, 0.0, 0.0, 0.0],
+            Self::BlackmanHarris => [0.358_75, 0.488_29, 0.141_28, 0.011_68, 0.0],
+            Self::FlatTop => [
+                0.215_578_95,
+                0.416_631_58,
+                0.277_263_16,
+                0.083_578_95,
+                0.006_947_37,
+            ],
+        }
+    }
+
+    // Periodic window of length `len` at index `n`
+    #[allow(clippy::cast_precision_loss)]
+    fn weight(self, n: usize, len: usize) -> f32 {
+        let x = 2.0 * PI * n as f32 / len as f32;
+        let [a0, a1, a2, a3, a4] = self.coefficients();
+        a0 - a1 * libm::cosf(x) + a2 * libm::cosf(2.0 * x) - a3 * libm::cosf(3.0 * x)
+            + a4 * libm::cosf(4.0 * x)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpectrumSource {
+    Accel,
+    Gyro,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SpectralPeak {
+    // Hz, interpolated between bins
+    pub frequency: f32,
+    // Amplitude of the bin in the units of the scaled samples
+    pub magnitude: f32,
+}
+
+// Single sided amplitude spectrum of the three axes of `N` samples.
+//
+// A sinusoid of amplitude A shows up with a peak of about A, slightly less
+// between bins unless the flat top window is used.
+#[derive(Clone, Debug)]
+pub struct Spectrum<const N: usize> {
+    sample_rate_hz: f32,
+    // Per axis, the first N / 2 + 1 bins are in use
+    magnitudes: [[f32; N]; 3],
+    // Converts summed squared amplitudes to mean square, corrects for the window
+    power_scale: f32,
+}
+impl<const N: usize> Default for Spectrum<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<const N: usize> Spectrum<N> {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            sample_rate_hz: 0.0,
+            magnitudes: [[0.0; N]; 3],
+            power_scale: 0.0,
+        }
+    }
+
+    /// Number of bins from 0 Hz to the Nyquist frequency
+    #[must_use]
+    pub const fn bins(&self) -> usize {
+        N / 2 + 1
+    }
+
+    /// Width of a bin in Hz
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn resolution_hz(&self) -> f32 {
+        self.sample_rate_hz / N as f32
+    }
+
+    /// Center frequency of `bin` in Hz
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn frequency(&self, bin: usize) -> f32 {
+        bin as f32 * self.resolution_hz()
+    }
+
+    /// Amplitude per bin of one axis
+    #[must_use]
+    pub fn magnitudes(&self, axis: Axis) -> &[f32] {
+        &self.magnitudes[axis as usize][..self.bins()]
+    }
+
+    /// Writes the highest local maxima of one axis to `out`, highest first, and
+    /// returns how many were found. The DC bin is never a peak.
+    pub fn peaks(&self, axis: Axis, out: &mut [SpectralPeak]) -> usize {
+        let magnitudes = self.magnitudes(axis);
+        let mut found = 0;
+        for bin in 1..magnitudes.len().saturating_sub(1) {
+            let (before, value, after) =
+                (magnitudes[bin - 1], magnitudes[bin], magnitudes[bin + 1]);
+            if value <= before || value < after {
+                continue;
+            }
+            // Insert sorted, dropping the lowest when full
+            let Some(position) = out[..found].iter().position(|peak| value > peak.magnitude) else {
+                if found < out.len() {
+                    out[found] = self.peak(bin, before, value, after);
+                    found += 1;
+                }
+                continue;
+            };
+            found = (found + 1).min(out.len());
+            out[position..found].rotate_right(1);
+            out[position] = self.peak(bin, before, value, after);
+        }
+        found
+    }
+
+    /// RMS per axis of the content between `low_hz` and `high_hz`, including the
+    /// bins whose center lies within the band
+    #[must_use]
+    pub fn band_rms(&self, low_hz: f32, high_hz: f32) -> Vec3<f32> {
+        let last = self.bins() - 1;
+        let [x, y, z] = self.magnitudes.each_ref().map(|magnitudes| {
+            let sum: f32 = magnitudes[..=last]
+                .iter()
+                .enumerate()
+                .filter(|&(bin, _)| (low_hz..=high_hz).contains(&self.frequency(bin)))
+                .map(|(bin, magnitude)| {
+                    // Interior bins carry the energy of both halves of the spectrum
+                    let share = if bin == 0 || bin == last { 1.0 } else { 0.5 };
+                    magnitude * magnitude * share
+                })
+                .sum();
+            libm::sqrtf(sum * self.power_scale)
+        });
+        Vec3 { x, y, z }
+    }
+
+    // Peak with its frequency refined by fitting a parabola through three bins
+    #[allow(clippy::cast_precision_loss)]
+    fn peak(&self, bin: usize, before: f32, value: f32, after: f32) -> SpectralPeak {
+        let curvature = before - 2.0 * value + after;
+        let offset = if curvature < 0.0 {
+            0.5 * (before - after) / curvature
+        } else {
+            0.0
+        };
+        SpectralPeak {
+            frequency: (bin as f32 + offset) * self.resolution_hz(),
+            magnitude: value,
+        }
+    }
+}
+
+// Spectral analysis of `N` evenly spaced samples, `N` a power of two.
+//
+// Works in place on the stack with a radix-2 FFT, one axis at a time. The mean
+// is removed before windowing by default so gravity and bias do not leak into
+// the lowest bins.
+pub struct SpectrumAnalyzer<const N: usize> {
+    sample_rate_hz: f32,
+    window: Window,
+    scale: f32,
+    remove_mean: bool,
+}
+impl<const N: usize> SpectrumAnalyzer<N> {
+    #[must_use]
+    pub const fn new(sample_rate_hz: f32) -> Self {
+        Self {
+            sample_rate_hz,
+            window: Window::Hann,
+            scale: 1.0,
+            remove_mean: true,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_window(mut self, window: Window) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Multiplies every sample by `scale`, e.g. `1.0 / AccelConfig1::sensitivity()`
+    /// to get spectra of raw accelerometer samples in g
+    #[must_use]
+    pub const fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Keeps the mean of the samples, it then shows up in the DC bin
+    #[must_use]
+    pub const fn with_mean(mut self) -> Self {
+        self.remove_mean = false;
+        self
+    }
+
+    /// Computes the spectrum of the first `N` samples. Returns false, leaving
+    /// `spectrum` untouched, if there are fewer samples or `N` is not a power of two.
+    pub fn analyze<S: AxisSample>(&self, samples: &[S], spectrum: &mut Spectrum<N>) -> bool {
+        self.compute(&samples.iter().map(AxisSample::axes), spectrum)
+    }
+
+    /// Computes the spectrum of the first `N` FIFO frames that carry `source`,
+    /// as captured by `FifoSnapshot`. Returns false if there are fewer.
+    pub fn analyze_frames(
+        &self,
+        frames: &[FifoFrame],
+        source: SpectrumSource,
+        spectrum: &mut Spectrum<N>,
+    ) -> bool {
+        let samples = frames.iter().filter_map(move |frame| match source {
+            SpectrumSource::Accel => frame.accel.as_ref().map(AxisSample::axes),
+            SpectrumSource::Gyro => frame.gyro.as_ref().map(AxisSample::axes),
+        });
+        self.compute(&samples, spectrum)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn compute<T>(&self, samples: &T, spectrum: &mut Spectrum<N>) -> bool
+    where
+        T: Iterator<Item = [f32; 3]> + Clone,
+    {
+        if !N.is_power_of_two() || N < 2 || samples.clone().take(N).count() < N {
+            return false;
+        }
+        let (mut sum, mut sum_squares) = (0.0, 0.0);
+        for n in 0..N {
+            let weight = self.window.weight(n, N);
+            sum += weight;
+            sum_squares += weight * weight;
+        }
+
+        let mut real = [0.0_f32; N];
+        let mut imaginary = [0.0_f32; N];
+        for (axis, magnitudes) in spectrum.magnitudes.iter_mut().enumerate() {
+            for (value, sample) in real.iter_mut().zip(samples.clone()) {
+                *value = sample[axis] * self.scale;
+            }
+            if self.remove_mean {
+                let mean = real.iter().sum::<f32>() / N as f32;
+                for value in &mut real {
+                    *value -= mean;
+                }
+            }
+            for (n, value) in real.iter_mut().enumerate() {
+                *value *= self.window.weight(n, N);
+            }
+            imaginary.fill(0.0);
+            fft(&mut real, &mut imaginary);
+            for (bin, magnitude) in magnitudes.iter_mut().enumerate().take(N / 2 + 1) {
+                let amplitude = libm::hypotf(real[bin], imaginary[bin]) / sum;
+                *magnitude = if bin == 0 || bin == N / 2 {
+                    amplitude
+                } else {
+                    2.0 * amplitude
+                };
+            }
+        }
+        spectrum.sample_rate_hz = self.sample_rate_hz;
+        spectrum.power_scale = sum * sum / (N as f32 * sum_squares);
+        true
+    }
+}
+
+// In place iterative radix-2 decimation in time FFT, the length must be a power of two
+#[allow(clippy::cast_precision_loss)]
+fn fft(real: &mut [f32], imaginary: &mut [f32]) {
+    let len = real.len();
+    let bits = len.trailing_zeros();
+    for i in 0..len {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+    let mut size = 2;
+    while size <= len {
+        let half = size / 2;
+        for k in 0..half {
+            let (sin, cos) = libm::sincosf(-2.0 * PI * k as f32 / size as f32);
+            for start in (0..len).step_by(size) {
+                let (a, b) = (start + k, start + k + half);
+                let t_real = real[b] * cos - imaginary[b] * sin;
+                let t_imaginary = real[b] * sin + imaginary[b] * cos;
+                real[b] = real[a] - t_real;
+                imaginary[b] = imaginary[a] - t_imaginary;
+                real[a] += t_real;
+                imaginary[a] += t_imaginary;
+            }
+        }
+        size *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Noise;
+
+    const RATE: f32 = 1000.0;
+
+    // Tone of `amplitude` at `frequency` on X, a constant on Y and `noise` on Z
+    #[allow(clippy::cast_precision_loss)]
+    fn signal<const N: usize>(tones: &[(f32, f32)]) -> [Vec3<f32>; N] {
+        let mut noise = Noise::new(11);
+        core::array::from_fn(|n| {
+            let t = n as f32 / RATE;
+            let x = tones
+                .iter()
+                .map(|&(frequency, amplitude)| amplitude * libm::sinf(2.0 * PI * frequency * t))
+                .sum();
+            Vec3::new(x, 0.5, noise.uniform())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::cast_precision_loss)]
+    fn fft_matches_direct_transform() {
+        let mut noise = Noise::new(5);
+        let input: [f32; 16] = core::array::from_fn(|_| noise.uniform());
+        let mut real = input;
+        let mut imaginary = [0.0; 16];
+        fft(&mut real, &mut imaginary);
+        for k in 0..16 {
+            let (mut expected_real, mut expected_imaginary) = (0.0, 0.0);
+            for (n, value) in input.iter().enumerate() {
+                let angle = -2.0 * PI * (k * n) as f32 / 16.0;
+                expected_real += value * libm::cosf(angle);
+                expected_imaginary += value * libm::sinf(angle);
+            }
+            assert!((real[k] - expected_real).abs() < 1e-4);
+            assert!((imaginary[k] - expected_imaginary).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn finds_tone_amplitude_and_frequency() {
+        let samples = signal::<256>(&[(125.0, 2.0)]);
+        let mut spectrum = Spectrum::<256>::new();
+        assert!(SpectrumAnalyzer::new(RATE).analyze(&samples, &mut spectrum));
+        assert_eq!(spectrum.bins(), 129);
+        assert!((spectrum.resolution_hz() - 3.906_25).abs() < 1e-6);
+        let mut peaks = [SpectralPeak::default(); 1];
+        assert_eq!(spectrum.peaks(Axis::X, &mut peaks), 1);
+        assert!((peaks[0].frequency - 125.0).abs() < 0.01, "{peaks:?}");
+        assert!((peaks[0].magnitude - 2.0).abs() < 1e-3, "{peaks:?}");
+        // Mean removed, nothing left of the constant axis
+        assert!(spectrum.magnitudes(Axis::Y).iter().all(|&m| m < 1e-5));
+
+        // Between bins the flat top window keeps the amplitude
+        let samples = signal::<256>(&[(101.7, 2.0)]);
+        SpectrumAnalyzer::new(RATE)
+            .with_window(Window::FlatTop)
+            .analyze(&samples, &mut spectrum);
+        spectrum.peaks(Axis::X, &mut peaks);
+        assert!((peaks[0].frequency - 101.7).abs() < 1.0, "{peaks:?}");
+        assert!((peaks[0].magnitude - 2.0).abs() < 0.02, "{peaks:?}");
+    }
+
+    #[test]
+    fn sorts_peaks_and_measures_band_rms() {
+        let samples = signal::<512>(&[(50.0, 0.5), (200.0, 1.5), (350.0, 1.0)]);
+        let mut spectrum = Spectrum::<512>::new();
+        SpectrumAnalyzer::new(RATE)
+            .with_scale(2.0)
+            .analyze(&samples, &mut spectrum);
+        let mut peaks = [SpectralPeak::default(); 2];
+        assert_eq!(spectrum.peaks(Axis::X, &mut peaks), 2);
+        assert!((peaks[0].frequency - 200.0).abs() < 1.0, "{peaks:?}");
+        assert!((peaks[1].frequency - 350.0).abs() < 1.0, "{peaks:?}");
+
+        // A tone of amplitude A has an RMS of A / √2
+        let rms = spectrum.band_rms(150.0, 250.0);
+        assert!((rms.x - 3.0 / libm::sqrtf(2.0)).abs() < 0.05, "{rms:?}");
+        let all = spectrum.band_rms(0.0, RATE / 2.0);
+        let expected = libm::sqrtf(1.0 + 9.0 + 4.0) / libm::sqrtf(2.0);
+        assert!((all.x - expected).abs() < 0.05, "{all:?}");
+        // Uniform noise in [-2, 2) has an RMS of 2 / √3
+        assert!((all.z - 2.0 / libm::sqrtf(3.0)).abs() < 0.1, "{all:?}");
+    }
+
+    #[test]
+    fn keeps_mean_on_request_and_rejects_short_input() {
+        let samples = signal::<64>(&[]);
+        let mut spectrum = Spectrum::<64>::new();
+        SpectrumAnalyzer::new(RATE)
+            .with_mean()
+            .with_window(Window::Rectangular)
+            .analyze(&samples, &mut spectrum);
+        assert!((spectrum.magnitudes(Axis::Y)[0] - 0.5).abs() < 1e-5);
+
+        let mut longer = Spectrum::<128>::new();
+        assert!(!SpectrumAnalyzer::new(RATE).analyze(&samples, &mut longer));
+        let mut odd = Spectrum::<48>::new();
+        assert!(!SpectrumAnalyzer::new(RATE).analyze(&samples, &mut odd));
+    }
+}
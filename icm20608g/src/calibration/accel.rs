@@ -0,0 +1,157 @@
+use embedded_hal::{delay::DelayNs, i2c::I2c};
+
+use super::{AxisStats, CalibrationError};
+use crate::structs::{
+    AccelConfig1, AccelMeasurements, AccelOffset, ReadRegister, Vec3, WriteRegister,
+};
+
+// AccelOffset LSBs per g, the registers use 0.98 mg steps regardless of full scale
+const OFFSET_LSB_PER_G: f32 = 1024.0;
+
+// Accelerometer offset calibration for a device lying level with Z pointing up.
+// The measured residual is added to the factory trim already in `AccelOffset`.
+pub struct AccelOffsetCalibration {
+    // Number of samples averaged
+    pub samples: u16,
+    // Time between samples, should not be shorter than the output data rate
+    pub sample_interval_us: u32,
+    // Largest per axis variance in g^2 that is accepted as stationary
+    pub max_variance: f32,
+}
+impl Default for AccelOffsetCalibration {
+    fn default() -> Self {
+        Self {
+            samples: 500,
+            sample_interval_us: 1_000,
+            max_variance: 0.000_1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AccelOffsetResult {
+    // Offsets written to the device
+    pub offset: AccelOffset,
+    // Deviation from (0, 0, 1) g that was measured with the previous offsets
+    pub residual: Vec3<f32>,
+    // Standard deviation of the samples in g, lower is better
+    pub noise: Vec3<f32>,
+}
+
+impl AccelOffsetCalibration {
+    /// Averages `samples` accelerometer readings at the configured full scale, checks that
+    /// the device was still and adjusts the existing `AccelOffset` by the residual.
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device, or with
+    /// `CalibrationError::NotStationary` if the variance of any axis exceeds
+    /// `max_variance`. Nothing is written in that case.
+    pub fn run<I: I2c, D: DelayNs>(
+        &self,
+        i2c: &mut I,
+        delay: &mut D,
+    ) -> Result<AccelOffsetResult, CalibrationError<I::Error>> {
+        let accel_config = AccelConfig1::new(i2c).map_err(CalibrationError::I2c)?;
+        let current = AccelOffset::new(i2c).map_err(CalibrationError::I2c)?;
+        let sensitivity = accel_config.sensitivity();
+
+        let mut stats = AxisStats::default();
+        for _ in 0..self.samples.max(2) {
+            let sample = AccelMeasurements::new(i2c).map_err(CalibrationError::I2c)?;
+            stats.push([
+                f32::from(sample.x),
+                f32::from(sample.y),
+                f32::from(sample.z),
+            ]);
+            delay.delay_us(self.sample_interval_us);
+        }
+
+        let variance = stats.variance().map(|v| v / (sensitivity * sensitivity));
+        if variance.iter().any(|&v| v > self.max_variance) {
+            return Err(CalibrationError::NotStationary);
+        }
+
+        let [mean_x, mean_y, mean_z] = stats.mean().map(|mean| mean / sensitivity);
+        let residual = Vec3 {
+            x: mean_x,
+            y: mean_y,
+            z: mean_z - 1.0,
+        };
+        let offset = AccelOffset {
+            x_offs: add_offset(current.x_offs, residual.x),
+            y_offs: add_offset(current.y_offs, residual.y),
+            z_offs: add_offset(current.z_offs, residual.z),
+        };
+        offset.write(i2c).map_err(CalibrationError::I2c)?;
+
+        let [noise_x, noise_y, noise_z] = variance.map(libm::sqrtf);
+        Ok(AccelOffsetResult {
+            offset,
+            residual,
+            noise: Vec3 {
+                x: noise_x,
+                y: noise_y,
+                z: noise_z,
+            },
+        })
+    }
+}
+
+// Subtracts the residual (in g) from a 15 bit offset register value
+#[allow(clippy::cast_possible_truncation)]
+fn add_offset(current: i16, residual: f32) -> i16 {
+    let corrected = f32::from(current) - libm::roundf(residual * OFFSET_LSB_PER_G);
+    corrected.clamp(-16384.0, 16383.0) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeDevice, NoDelay};
+
+    fn set_accel(device: &mut FakeDevice, raw: [i16; 3]) {
+        for (i, value) in raw.into_iter().enumerate() {
+            device.registers[0x3B + 2 * i..0x3D + 2 * i].copy_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn run_adds_the_residual_to_the_factory_trim() {
+        // Residual of (20, -42, 10) / 2048 g, which is (10, -21, 5) offset LSBs
+        // at every full scale
+        for full_scale_select in 0..4_u8 {
+            let mut device = FakeDevice::new();
+            device.registers[0x1C] = full_scale_select << 3;
+            AccelOffset {
+                x_offs: 500,
+                y_offs: -300,
+                z_offs: 1000,
+            }
+            .write(&mut device)
+            .unwrap();
+            let lsb_per_g = 16384 >> full_scale_select;
+            let scale = lsb_per_g / 2048;
+            set_accel(
+                &mut device,
+                [20 * scale, -42 * scale, lsb_per_g + 10 * scale],
+            );
+
+            let calibration = AccelOffsetCalibration {
+                samples: 10,
+                ..AccelOffsetCalibration::default()
+            };
+            let result = calibration.run(&mut device, &mut NoDelay).unwrap();
+            // Gravity on Z is not part of the residual
+            assert!((result.residual.z - 10.0 / 2048.0).abs() < 1e-6);
+            assert_eq!(
+                (
+                    result.offset.x_offs,
+                    result.offset.y_offs,
+                    result.offset.z_offs
+                ),
+                (490, -279, 995)
+            );
+            assert_eq!(AccelOffset::new(&mut device).unwrap(), result.offset);
+        }
+    }
+}
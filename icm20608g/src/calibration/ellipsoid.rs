@@ -0,0 +1,307 @@
+use super::{invert, solve_linear, AccelCalibration, AxisStats};
+use crate::structs::Vec3;
+
+// Number of parameters of a general ellipsoid
+const PARAMS: usize = 9;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EllipsoidFit {
+    pub calibration: AccelCalibration,
+    // RMS deviation of the corrected points from 1 g
+    pub residual_rms: f32,
+    // Largest deviation of a corrected point from 1 g
+    pub residual_max: f32,
+}
+
+// Accelerometer calibration from static poses in arbitrary orientations.
+//
+// While the user slowly turns the device, samples in g are pushed and every
+// stable window becomes one point, as long as it is at least `min_separation`
+// away from the points already collected. Up to `N` points are kept. An
+// ellipsoid is then fitted by least squares and the correction that maps it
+// onto the unit sphere is returned.
+pub struct EllipsoidCalibration<const N: usize> {
+    // Samples that must be stable for a point to be taken
+    window: u32,
+    // Largest per axis variance in g^2 that counts as stable
+    max_variance: f32,
+    // Smallest distance in g between two points
+    min_separation: f32,
+    stats: AxisStats,
+    points: [[f32; 3]; N],
+    len: usize,
+}
+impl<const N: usize> EllipsoidCalibration<N> {
+    #[must_use]
+    pub fn new(window: u32, max_variance: f32, min_separation: f32) -> Self {
+        Self {
+            window: window.max(2),
+            max_variance,
+            min_separation,
+            stats: AxisStats::default(),
+            points: [[0.0; 3]; N],
+            len: 0,
+        }
+    }
+
+    /// Number of points collected so far. At least 9 are needed for a fit,
+    /// more points spread over all orientations give a better one.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[must_use]
+    pub const fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.stats = AxisStats::default();
+    }
+
+    /// Feeds one accelerometer sample in g. Returns true if a new point was
+    /// taken with this sample.
+    pub fn push(&mut self, accel: Vec3<f32>) -> bool {
+        self.stats.push([accel.x, accel.y, accel.z]);
+        if self.stats.count() < self.window {
+            return false;
+        }
+        let stats = core::mem::take(&mut self.stats);
+        if stats.variance().iter().any(|&v| v > self.max_variance) {
+            return false;
+        }
+        let [x, y, z] = stats.mean();
+        self.add_point(Vec3 { x, y, z })
+    }
+
+    /// Adds an already averaged static point in g. Returns false if the buffer
+    /// is full or the point is too close to an existing one.
+    pub fn add_point(&mut self, accel: Vec3<f32>) -> bool {
+        let point = [accel.x, accel.y, accel.z];
+        let min_distance2 = self.min_separation * self.min_separation;
+        let too_close = self.points[..self.len].iter().any(|other| {
+            let dx = other[0] - point[0];
+            let dy = other[1] - point[1];
+            let dz = other[2] - point[2];
+            dx * dx + dy * dy + dz * dz < min_distance2
+        });
+        if self.is_full() || too_close {
+            return false;
+        }
+        self.points[self.len] = point;
+        self.len += 1;
+        true
+    }
+
+    /// Fits `a x² + b y² + c z² + 2f yz + 2g xz + 2h xy + 2p x + 2q y + 2r z = 1`
+    /// to the collected points. The center of the ellipsoid is the bias and the
+    /// symmetric square root of its normalized shape matrix is the correction.
+    ///
+    /// Returns None with fewer than 9 points, or if the points do not describe
+    /// an ellipsoid (e.g. they all lie in one plane).
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::many_single_char_names
+    )]
+    #[must_use]
+    pub fn solve(&self) -> Option<EllipsoidFit> {
+        let points = &self.points[..self.len];
+        if points.len() < PARAMS {
+            return None;
+        }
+
+        let mut normal = [[0.0_f64; PARAMS]; PARAMS];
+        let mut rhs = [0.0_f64; PARAMS];
+        for point in points {
+            let [x, y, z] = point.map(f64::from);
+            let row = [
+                x * x,
+                y * y,
+                z * z,
+                2.0 * y * z,
+                2.0 * x * z,
+                2.0 * x * y,
+                2.0 * x,
+                2.0 * y,
+                2.0 * z,
+            ];
+            for ((normal_row, rhs), row_i) in normal.iter_mut().zip(&mut rhs).zip(row) {
+                for (normal_value, value) in normal_row.iter_mut().zip(row) {
+                    *normal_value += row_i * value;
+                }
+                *rhs += row_i;
+            }
+        }
+        let [a, b, c, f, g, h, p, q, r] = solve_linear(normal, rhs)?;
+
+        let shape = [[a, h, g], [h, b, f], [g, f, c]];
+        let linear = [p, q, r];
+        let inverse = invert(&shape)?;
+        let center =
+            inverse.map(|row| -(row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2]));
+        let k = 1.0
+            + (0..3)
+                .map(|i| {
+                    (0..3)
+                        .map(|j| center[i] * shape[i][j] * center[j])
+                        .sum::<f64>()
+                })
+                .sum::<f64>();
+        if k <= 0.0 {
+            return None;
+        }
+
+        let (eigenvalues, eigenvectors) = jacobi(shape.map(|row| row.map(|value| value / k)));
+        if eigenvalues.iter().any(|&value| value <= 0.0) {
+            return None;
+        }
+        let roots = eigenvalues.map(libm::sqrt);
+        let mut matrix = [[0.0_f32; 3]; 3];
+        for (i, matrix_row) in matrix.iter_mut().enumerate() {
+            for (j, value) in matrix_row.iter_mut().enumerate() {
+                *value = (0..3)
+                    .map(|e| eigenvectors[i][e] * roots[e] * eigenvectors[j][e])
+                    .sum::<f64>() as f32;
+            }
+        }
+
+        let calibration = AccelCalibration {
+            bias: Vec3 {
+                x: center[0] as f32,
+                y: center[1] as f32,
+                z: center[2] as f32,
+            },
+            matrix,
+        };
+        let mut sum_squares = 0.0;
+        let mut residual_max = 0.0_f32;
+        for point in points {
+            let corrected = calibration.correct(Vec3 {
+                x: point[0],
+                y: point[1],
+                z: point[2],
+            });
+            let magnitude = libm::sqrtf(
+                corrected.x * corrected.x + corrected.y * corrected.y + corrected.z * corrected.z,
+            );
+            let residual = magnitude - 1.0;
+            sum_squares += residual * residual;
+            residual_max = residual_max.max(residual.abs());
+        }
+        Some(EllipsoidFit {
+            calibration,
+            residual_rms: libm::sqrtf(sum_squares / points.len() as f32),
+            residual_max,
+        })
+    }
+}
+
+// Eigen decomposition of a symmetric 3x3 matrix by cyclic Jacobi rotations.
+// Returns the eigenvalues and the eigenvectors as matching columns.
+#[allow(clippy::many_single_char_names)]
+fn jacobi(mut a: [[f64; 3]; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    for _ in 0..50 {
+        let off_diagonal = a[0][1] * a[0][1] + a[0][2] * a[0][2] + a[1][2] * a[1][2];
+        if off_diagonal < 1e-24 {
+            break;
+        }
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if a[p][q] == 0.0 {
+                continue;
+            }
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+            let t = theta.signum() / (theta.abs() + libm::sqrt(theta * theta + 1.0));
+            let c = 1.0 / libm::sqrt(t * t + 1.0);
+            let s = t * c;
+            for row in &mut a {
+                let (kp, kq) = (row[p], row[q]);
+                row[p] = c * kp - s * kq;
+                row[q] = s * kp + c * kq;
+            }
+            let (row_p, row_q) = (a[p], a[q]);
+            for k in 0..3 {
+                a[p][k] = c * row_p[k] - s * row_q[k];
+                a[q][k] = s * row_p[k] + c * row_q[k];
+            }
+            for row in &mut v {
+                let (kp, kq) = (row[p], row[q]);
+                row[p] = c * kp - s * kq;
+                row[q] = s * kp + c * kq;
+            }
+        }
+    }
+    ([a[0][0], a[1][1], a[2][2]], v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Symmetric scale and misalignment, so the fitted correction is its inverse
+    const SENSITIVITY: [[f32; 3]; 3] =
+        [[1.05, 0.02, -0.01], [0.02, 0.97, 0.03], [-0.01, 0.03, 1.02]];
+    const BIAS: [f32; 3] = [0.04, -0.03, 0.06];
+
+    fn measure(direction: [f32; 3]) -> Vec3<f32> {
+        let norm = libm::sqrtf(direction.iter().map(|value| value * value).sum());
+        let [x, y, z] =
+            crate::calibration::mul_vec(&SENSITIVITY, direction.map(|value| value / norm));
+        Vec3::new(x + BIAS[0], y + BIAS[1], z + BIAS[2])
+    }
+
+    fn directions() -> impl Iterator<Item = [f32; 3]> {
+        // Cube corners, edge and face centres around the origin
+        (0_u8..27)
+            .filter(|&i| i != 13)
+            .map(|i| [i % 3, i / 3 % 3, i / 9].map(|value| f32::from(value) - 1.0))
+    }
+
+    #[test]
+    fn recovers_bias_and_correction() {
+        let mut calibration = EllipsoidCalibration::<32>::new(10, 1e-4, 0.1);
+        for direction in directions() {
+            assert!(calibration.add_point(measure(direction)));
+        }
+        let fit = calibration.solve().unwrap();
+        assert!(fit.residual_max < 1e-4, "{fit:?}");
+        let bias = fit.calibration.bias;
+        for (fitted, expected) in [bias.x, bias.y, bias.z].into_iter().zip(BIAS) {
+            assert!((fitted - expected).abs() < 1e-4, "{bias:?}");
+        }
+        let corrected = fit.calibration.correct(measure([0.0, 0.0, 1.0]));
+        assert!(
+            (corrected - Vec3::new(0.0, 0.0, 1.0)).norm() < 1e-4,
+            "{corrected:?}"
+        );
+    }
+
+    #[test]
+    fn rejects_degenerate_points() {
+        let mut calibration = EllipsoidCalibration::<32>::new(10, 1e-4, 0.1);
+        for direction in directions().take(8) {
+            calibration.add_point(measure(direction));
+        }
+        assert_eq!(calibration.len(), 8);
+        assert!(calibration.solve().is_none());
+
+        let mut planar = EllipsoidCalibration::<32>::new(10, 1e-4, 0.1);
+        for direction in directions().filter(|direction| direction[2].abs() < 0.5) {
+            planar.add_point(measure(direction));
+        }
+        for i in 0_u8..8 {
+            let angle = f32::from(i) * core::f32::consts::FRAC_PI_4 + 0.3;
+            planar.add_point(measure([libm::cosf(angle), libm::sinf(angle), 0.0]));
+        }
+        assert!(planar.len() >= 9);
+        assert!(planar.solve().is_none());
+    }
+}
@@ -0,0 +1,213 @@
+use embedded_hal::{delay::DelayNs, i2c::I2c};
+
+use super::{AxisStats, CalibrationError};
+use crate::structs::{
+    GyroConfig, GyroOffset, GyroscopeMeasurements, ReadRegister, Vec3, WriteRegister,
+};
+
+// Stationary gyroscope bias calibration. The device must be kept still while
+// `run` collects samples.
+pub struct GyroBiasCalibration {
+    // Number of samples averaged
+    pub samples: u16,
+    // Time between samples, should not be shorter than the output data rate
+    pub sample_interval_us: u32,
+    // Largest per axis variance in (º/s)^2 that is accepted as stationary
+    pub max_variance: f32,
+}
+impl Default for GyroBiasCalibration {
+    fn default() -> Self {
+        Self {
+            samples: 500,
+            sample_interval_us: 1_000,
+            max_variance: 0.25,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GyroBiasResult {
+    // Offsets written to the device
+    pub offset: GyroOffset,
+    // Bias that was measured with the previous offsets, in º/s
+    pub bias: Vec3<f32>,
+    // Standard deviation of the samples in º/s, lower is better
+    pub noise: Vec3<f32>,
+}
+
+impl GyroBiasCalibration {
+    /// Averages `samples` gyroscope readings at the configured full scale, checks that
+    /// the device was still and corrects the existing `GyroOffset` by the measured bias.
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device, or with
+    /// `CalibrationError::NotStationary` if the variance of any axis exceeds
+    /// `max_variance`. Nothing is written in that case.
+    pub fn run<I: I2c, D: DelayNs>(
+        &self,
+        i2c: &mut I,
+        delay: &mut D,
+    ) -> Result<GyroBiasResult, CalibrationError<I::Error>> {
+        let gyro_config = GyroConfig::new(i2c).map_err(CalibrationError::I2c)?;
+        let current = GyroOffset::new(i2c).map_err(CalibrationError::I2c)?;
+        let sensitivity = gyro_config.sensitivity();
+
+        let mut stats = AxisStats::default();
+        for _ in 0..self.samples.max(2) {
+            let sample = GyroscopeMeasurements::new(i2c).map_err(CalibrationError::I2c)?;
+            stats.push([
+                f32::from(sample.x),
+                f32::from(sample.y),
+                f32::from(sample.z),
+            ]);
+            delay.delay_us(self.sample_interval_us);
+        }
+
+        let variance = stats.variance().map(|v| v / (sensitivity * sensitivity));
+        if variance.iter().any(|&v| v > self.max_variance) {
+            return Err(CalibrationError::NotStationary);
+        }
+
+        let [bias_x, bias_y, bias_z] = stats.mean().map(|bias| bias / sensitivity);
+        let bias = Vec3 {
+            x: bias_x,
+            y: bias_y,
+            z: bias_z,
+        };
+        let offset = current.compensate(bias);
+        offset.write(i2c).map_err(CalibrationError::I2c)?;
+
+        let [noise_x, noise_y, noise_z] = variance.map(libm::sqrtf);
+        Ok(GyroBiasResult {
+            offset,
+            bias,
+            noise: Vec3 {
+                x: noise_x,
+                y: noise_y,
+                z: noise_z,
+            },
+        })
+    }
+}
+
+// Reads `GyroOffset`, adjusts it to additionally cancel `bias` in rad/s and
+// writes it back. Returns the offsets written.
+pub(crate) fn commit_bias<I: I2c>(i2c: &mut I, bias: Vec3<f32>) -> Result<GyroOffset, I::Error> {
+    let offset = GyroOffset::new(i2c)?.compensate(Vec3::new(
+        bias.x.to_degrees(),
+        bias.y.to_degrees(),
+        bias.z.to_degrees(),
+    ));
+    offset.write(i2c)?;
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeDevice, NoDelay};
+    use embedded_hal::i2c::{ErrorType, Operation, SevenBitAddress};
+
+    // Gyroscope X output alternating between +1 and -1 º/s on every sample,
+    // counting the writes that carry data
+    struct Shaking {
+        device: FakeDevice,
+        toggle: bool,
+        writes: usize,
+    }
+    impl ErrorType for Shaking {
+        type Error = core::convert::Infallible;
+    }
+    impl I2c for Shaking {
+        fn transaction(
+            &mut self,
+            address: SevenBitAddress,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            if matches!(operations.first(), Some(Operation::Write(&[0x43]))) {
+                self.toggle = !self.toggle;
+            }
+            let x: i16 = if self.toggle { 131 } else { -131 };
+            self.device.registers[0x43..0x45].copy_from_slice(&x.to_be_bytes());
+            for operation in operations.iter() {
+                if matches!(operation, Operation::Write(bytes) if bytes.len() > 1) {
+                    self.writes += 1;
+                }
+            }
+            self.device.transaction(address, operations)
+        }
+    }
+
+    #[test]
+    fn run_adds_the_measured_bias_to_the_current_offsets() {
+        let mut device = FakeDevice::new();
+        GyroOffset {
+            xg_offs: 100,
+            yg_offs: -100,
+            zg_offs: 0,
+        }
+        .write(&mut device)
+        .unwrap();
+        // +1 and -1 º/s at ±250 º/s
+        device.registers[0x43..0x45].copy_from_slice(&131_i16.to_be_bytes());
+        device.registers[0x45..0x47].copy_from_slice(&(-131_i16).to_be_bytes());
+
+        let calibration = GyroBiasCalibration {
+            samples: 10,
+            ..GyroBiasCalibration::default()
+        };
+        let result = calibration.run(&mut device, &mut NoDelay).unwrap();
+        assert!((result.bias.x - 1.0).abs() < 1e-6);
+        assert!((result.bias.y + 1.0).abs() < 1e-6);
+        assert!(result.bias.z.abs() < 1e-6);
+        assert!(result.noise.x < 1e-6);
+        // 32.8 LSB/(º/s) on top of the existing trim
+        assert_eq!(
+            (
+                result.offset.xg_offs,
+                result.offset.yg_offs,
+                result.offset.zg_offs
+            ),
+            (67, -67, 0)
+        );
+        assert_eq!(GyroOffset::new(&mut device).unwrap(), result.offset);
+    }
+
+    #[test]
+    fn run_writes_nothing_when_moving() {
+        let mut shaking = Shaking {
+            device: FakeDevice::new(),
+            toggle: false,
+            writes: 0,
+        };
+        let calibration = GyroBiasCalibration {
+            samples: 10,
+            ..GyroBiasCalibration::default()
+        };
+        assert!(matches!(
+            calibration.run(&mut shaking, &mut NoDelay),
+            Err(CalibrationError::NotStationary)
+        ));
+        assert_eq!(shaking.writes, 0);
+    }
+
+    #[test]
+    fn commit_bias_adjusts_the_current_offsets() {
+        let mut device = FakeDevice::new();
+        GyroOffset {
+            xg_offs: 100,
+            yg_offs: -100,
+            zg_offs: 0,
+        }
+        .write(&mut device)
+        .unwrap();
+        let bias = Vec3::new(1.0_f32.to_radians(), -0.5_f32.to_radians(), 0.0);
+        let written = commit_bias(&mut device, bias).unwrap();
+        // 32.8 LSB/(º/s)
+        assert_eq!(
+            (written.xg_offs, written.yg_offs, written.zg_offs),
+            (67, -84, 0)
+        );
+        assert_eq!(GyroOffset::new(&mut device).unwrap(), written);
+    }
+}
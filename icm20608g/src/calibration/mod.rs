@@ -0,0 +1,185 @@
+use crate::structs::{AccelConfig1, AccelMeasurements, Vec3};
+
+mod accel;
+mod ellipsoid;
+mod gyro;
+mod six_position;
+mod storage;
+mod thermal;
+pub use accel::{AccelOffsetCalibration, AccelOffsetResult};
+pub use ellipsoid::{EllipsoidCalibration, EllipsoidFit};
+pub(crate) use gyro::commit_bias;
+pub use gyro::{GyroBiasCalibration, GyroBiasResult};
+pub use six_position::SixPositionCalibration;
+pub use storage::{BlobError, CalibrationData, CALIBRATION_BLOB_SIZE, CALIBRATION_VERSION};
+pub use thermal::{ThermalCalibration, ThermalModel, ThermalOffsetUpdater, MAX_THERMAL_DEGREE};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalibrationError<E> {
+    // Communication with the device failed
+    I2c(E),
+    // The measured noise was above the allowed threshold, the device was
+    // most likely moved during calibration
+    NotStationary,
+    // Stored calibration belongs to a different device variant
+    DeviceMismatch,
+}
+
+// Software accelerometer correction: corrected = matrix * (measured - bias).
+// The matrix combines the per axis scale factors on its diagonal with the
+// cross-axis misalignment off the diagonal. Values are in g.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccelCalibration {
+    pub bias: Vec3<f32>,
+    // Row major
+    pub matrix: [[f32; 3]; 3],
+}
+impl Default for AccelCalibration {
+    fn default() -> Self {
+        Self {
+            bias: Vec3::default(),
+            matrix: IDENTITY,
+        }
+    }
+}
+impl AccelCalibration {
+    /// Corrects an acceleration in g
+    #[must_use]
+    pub fn correct(&self, accel: Vec3<f32>) -> Vec3<f32> {
+        let [x, y, z] = mul_vec(
+            &self.matrix,
+            [
+                accel.x - self.bias.x,
+                accel.y - self.bias.y,
+                accel.z - self.bias.z,
+            ],
+        );
+        Vec3 { x, y, z }
+    }
+
+    /// Converts a raw measurement to g and corrects it
+    #[must_use]
+    pub fn apply(&self, measurement: &AccelMeasurements, config: &AccelConfig1) -> Vec3<f32> {
+        self.correct(measurement.to_g(config))
+    }
+}
+
+pub(crate) const IDENTITY: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+pub(crate) fn mul_vec(matrix: &[[f32; 3]; 3], vector: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2])
+}
+
+// Inverse of a 3x3 matrix, None if it is singular
+pub(crate) fn invert(m: &[[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let adjugate = [
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 1, 1, 2),
+        ],
+        [
+            -cofactor(1, 2, 0, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 1, 0, 2),
+        ],
+        [
+            cofactor(1, 2, 0, 1),
+            -cofactor(0, 2, 0, 1),
+            cofactor(0, 1, 0, 1),
+        ],
+    ];
+    let determinant =
+        m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    Some(adjugate.map(|row| row.map(|value| value / determinant)))
+}
+
+// Solves `matrix * x = rhs` by Gaussian elimination with partial pivoting
+pub(crate) fn solve_linear<const N: usize>(
+    mut matrix: [[f64; N]; N],
+    mut rhs: [f64; N],
+) -> Option<[f64; N]> {
+    for column in 0..N {
+        let pivot = (column..N)
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))?;
+        if matrix[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        rhs.swap(column, pivot);
+        for row in column + 1..N {
+            let pivot_row = matrix[column];
+            let factor = matrix[row][column] / pivot_row[column];
+            for (value, pivot_value) in matrix[row].iter_mut().zip(pivot_row).skip(column) {
+                *value -= factor * pivot_value;
+            }
+            rhs[row] -= factor * rhs[column];
+        }
+    }
+    let mut solution = [0.0; N];
+    for row in (0..N).rev() {
+        let sum: f64 = (row + 1..N).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (rhs[row] - sum) / matrix[row][row];
+    }
+    Some(solution)
+}
+
+// Running mean and variance of a 3 axis signal (Welford's algorithm)
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct AxisStats {
+    count: u32,
+    mean: [f32; 3],
+    m2: [f32; 3],
+}
+impl AxisStats {
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn push(&mut self, sample: [f32; 3]) {
+        self.count += 1;
+        let count = self.count as f32;
+        for ((mean, m2), value) in self.mean.iter_mut().zip(&mut self.m2).zip(sample) {
+            let delta = value - *mean;
+            *mean += delta / count;
+            *m2 += delta * (value - *mean);
+        }
+    }
+
+    pub(crate) const fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub(crate) const fn mean(&self) -> [f32; 3] {
+        self.mean
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn variance(&self) -> [f32; 3] {
+        let count = self.count.saturating_sub(1).max(1) as f32;
+        self.m2.map(|m2| m2 / count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::needless_range_loop)]
+    #[test]
+    fn invert_gives_inverse_or_none() {
+        let m = [[2.0, 1.0, 0.0], [0.5, 3.0, -1.0], [0.0, 0.25, 1.5]];
+        let inverse = invert(&m).unwrap();
+        for (i, row) in m.iter().enumerate() {
+            for j in 0..3 {
+                let product: f64 = (0..3).map(|k| row[k] * inverse[k][j]).sum();
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((product - expected).abs() < 1e-12);
+            }
+        }
+        assert!(invert(&[[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 1.0, 1.0]]).is_none());
+    }
+}
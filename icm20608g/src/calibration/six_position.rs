@@ -0,0 +1,251 @@
+use super::{invert, AccelCalibration, AxisStats};
+use crate::sample::Face;
+use crate::structs::Vec3;
+
+// Smallest reading in g on the vertical axis for a pose to count as a face
+const FACE_THRESHOLD: f32 = 0.8;
+
+// Face whose vertical axis dominates the reading, if any
+fn detect_face(accel: [f32; 3]) -> Option<Face> {
+    let (axis, value) = accel
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))?;
+    if value.abs() < FACE_THRESHOLD {
+        return None;
+    }
+    Some(match (axis, value > 0.0) {
+        (0, true) => Face::XUp,
+        (0, false) => Face::XDown,
+        (1, true) => Face::YUp,
+        (1, false) => Face::YDown,
+        (_, true) => Face::ZUp,
+        (_, false) => Face::ZDown,
+    })
+}
+
+// Six-face accelerometer calibration.
+//
+// Accelerometer samples in g are pushed while the user turns the device onto
+// each face in turn. A face is captured once `window` consecutive samples were
+// stable, after which `next_face` names the next one to prompt for. Faces may
+// be presented in any order. With all six captured, `solve` returns the bias,
+// scale factors and misalignment.
+pub struct SixPositionCalibration {
+    // Samples that must be stable for a face to be captured
+    window: u32,
+    // Largest per axis variance in g^2 that counts as stable
+    max_variance: f32,
+    stats: AxisStats,
+    faces: [Option<[f32; 3]>; 6],
+}
+impl SixPositionCalibration {
+    #[must_use]
+    pub fn new(window: u32, max_variance: f32) -> Self {
+        Self {
+            window: window.max(2),
+            max_variance,
+            stats: AxisStats::default(),
+            faces: [None; 6],
+        }
+    }
+
+    /// Next face that still has to be captured, None once all are done
+    #[must_use]
+    pub fn next_face(&self) -> Option<Face> {
+        Face::ALL
+            .into_iter()
+            .find(|&face| self.faces[face as usize].is_none())
+    }
+
+    #[must_use]
+    pub fn is_captured(&self, face: Face) -> bool {
+        self.faces[face as usize].is_some()
+    }
+
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.faces.iter().all(Option::is_some)
+    }
+
+    /// Discards the capture of a single face, e.g. if the user reports a mistake
+    pub fn recapture(&mut self, face: Face) {
+        self.faces[face as usize] = None;
+        self.stats = AxisStats::default();
+    }
+
+    /// Feeds one accelerometer sample in g. Returns the face that was captured
+    /// with this sample, if any.
+    pub fn push(&mut self, accel: Vec3<f32>) -> Option<Face> {
+        self.stats.push([accel.x, accel.y, accel.z]);
+        if self.stats.count() < self.window {
+            return None;
+        }
+        let stats = core::mem::take(&mut self.stats);
+        if stats.variance().iter().any(|&v| v > self.max_variance) {
+            return None;
+        }
+        let face = detect_face(stats.mean())?;
+        if self.is_captured(face) {
+            return None;
+        }
+        self.faces[face as usize] = Some(stats.mean());
+        Some(face)
+    }
+
+    /// Solves for bias, scale and misalignment once all faces are captured.
+    ///
+    /// With the measurement model `measured = S * true + bias`, the average of
+    /// opposite faces is the bias and half their difference is a column of `S`.
+    /// The correction matrix is the inverse of `S`.
+    #[allow(clippy::cast_possible_truncation)]
+    #[must_use]
+    pub fn solve(&self) -> Option<AccelCalibration> {
+        let face = |face: Face| self.faces[face as usize];
+        let pairs = [
+            (face(Face::XUp)?, face(Face::XDown)?),
+            (face(Face::YUp)?, face(Face::YDown)?),
+            (face(Face::ZUp)?, face(Face::ZDown)?),
+        ];
+
+        let mut bias = [0.0; 3];
+        let mut sensitivity = [[0.0_f64; 3]; 3];
+        for (column, (up, down)) in pairs.iter().enumerate() {
+            for row in 0..3 {
+                bias[row] += (up[row] + down[row]) / 6.0;
+                sensitivity[row][column] = f64::from(up[row] - down[row]) / 2.0;
+            }
+        }
+        Some(AccelCalibration {
+            bias: Vec3 {
+                x: bias[0],
+                y: bias[1],
+                z: bias[2],
+            },
+            matrix: invert(&sensitivity)?.map(|row| row.map(|value| value as f32)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::mul_vec;
+    use crate::test_support::Noise;
+
+    const BIAS: [f32; 3] = [0.03, -0.02, 0.05];
+    // Scale factors on the diagonal, misalignment off it
+    const SENSITIVITY: [[f32; 3]; 3] = [
+        [1.02, 0.01, -0.02],
+        [0.005, 0.98, 0.015],
+        [-0.01, 0.02, 1.01],
+    ];
+    const WINDOW: u32 = 50;
+
+    // Gravity in g in the sensor frame with `face` up
+    fn gravity(face: Face) -> [f32; 3] {
+        match face {
+            Face::XUp => [1.0, 0.0, 0.0],
+            Face::XDown => [-1.0, 0.0, 0.0],
+            Face::YUp => [0.0, 1.0, 0.0],
+            Face::YDown => [0.0, -1.0, 0.0],
+            Face::ZUp => [0.0, 0.0, 1.0],
+            Face::ZDown => [0.0, 0.0, -1.0],
+        }
+    }
+
+    fn measured(truth: [f32; 3]) -> [f32; 3] {
+        let scaled = mul_vec(&SENSITIVITY, truth);
+        [
+            scaled[0] + BIAS[0],
+            scaled[1] + BIAS[1],
+            scaled[2] + BIAS[2],
+        ]
+    }
+
+    // Pushes a window of slightly noisy samples, returning the last result
+    fn hold(
+        calibration: &mut SixPositionCalibration,
+        accel: [f32; 3],
+        noise: &mut Noise,
+        amplitude: f32,
+    ) -> Option<Face> {
+        let mut captured = None;
+        for _ in 0..WINDOW {
+            captured = calibration.push(Vec3::new(
+                accel[0] + amplitude * noise.normal(),
+                accel[1] + amplitude * noise.normal(),
+                accel[2] + amplitude * noise.normal(),
+            ));
+        }
+        captured
+    }
+
+    #[test]
+    #[allow(clippy::needless_range_loop)]
+    fn recovers_bias_scale_and_misalignment() {
+        let mut calibration = SixPositionCalibration::new(WINDOW, 1e-4);
+        let mut noise = Noise::new(7);
+        while let Some(face) = calibration.next_face() {
+            let captured = hold(&mut calibration, measured(gravity(face)), &mut noise, 1e-4);
+            assert_eq!(captured, Some(face));
+        }
+        assert!(calibration.is_complete());
+
+        let solved = calibration.solve().unwrap();
+        for (solved, expected) in [solved.bias.x, solved.bias.y, solved.bias.z]
+            .into_iter()
+            .zip(BIAS)
+        {
+            assert!((solved - expected).abs() < 1e-4, "{solved} vs {expected}");
+        }
+        // The correction undoes the sensitivity matrix
+        for row in 0..3 {
+            for column in 0..3 {
+                let product: f32 = (0..3)
+                    .map(|k| solved.matrix[row][k] * SENSITIVITY[k][column])
+                    .sum();
+                let expected = if row == column { 1.0 } else { 0.0 };
+                assert!((product - expected).abs() < 1e-3);
+            }
+        }
+        let [x, y, z] = measured([0.6, -0.48, 0.64]);
+        let corrected = solved.correct(Vec3::new(x, y, z));
+        assert!((corrected.x - 0.6).abs() < 1e-3);
+        assert!((corrected.y + 0.48).abs() < 1e-3);
+        assert!((corrected.z - 0.64).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rejects_unstable_windows_and_captured_faces() {
+        let mut calibration = SixPositionCalibration::new(WINDOW, 1e-4);
+        let mut noise = Noise::new(3);
+        let z_up = measured(gravity(Face::ZUp));
+
+        // Standard deviation of 0.05 g is far above the threshold
+        assert_eq!(hold(&mut calibration, z_up, &mut noise, 0.05), None);
+        assert!(!calibration.is_captured(Face::ZUp));
+
+        assert_eq!(
+            hold(&mut calibration, z_up, &mut noise, 1e-4),
+            Some(Face::ZUp)
+        );
+        assert_eq!(hold(&mut calibration, z_up, &mut noise, 1e-4), None);
+        // Tilted halfway between two faces
+        assert_eq!(
+            hold(
+                &mut calibration,
+                measured([0.7, 0.0, 0.7]),
+                &mut noise,
+                1e-4
+            ),
+            None
+        );
+        assert_eq!(calibration.next_face(), Some(Face::ZDown));
+        assert!(calibration.solve().is_none());
+
+        calibration.recapture(Face::ZUp);
+        assert!(!calibration.is_captured(Face::ZUp));
+    }
+}
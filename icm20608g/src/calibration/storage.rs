@@ -0,0 +1,355 @@
+use embedded_hal::i2c::I2c;
+
+use super::{AccelCalibration, CalibrationError, ThermalModel, IDENTITY, MAX_THERMAL_DEGREE};
+use crate::structs::{AccelOffset, GyroOffset, ReadRegister, Vec3, WhoAmI, WriteRegister};
+
+// Identifies a calibration blob
+const MAGIC: [u8; 2] = *b"IC";
+// Current layout of the blob, bumped whenever the layout changes
+pub const CALIBRATION_VERSION: u8 = 1;
+// Flag set when `CalibrationData::gyro_thermal` is present
+const FLAG_THERMAL: u8 = 1;
+
+// magic, version, device id, flags
+const HEADER_SIZE: usize = 5;
+// 2 x 3 i16 offsets, accel bias, 2 x 3x3 matrices, thermal reference and coefficients
+const PAYLOAD_SIZE: usize = 2 * 6 + 3 * 4 + 2 * 9 * 4 + 4 + 3 * (MAX_THERMAL_DEGREE + 1) * 4;
+// Size in bytes of the blob written by `CalibrationData::to_bytes`
+pub const CALIBRATION_BLOB_SIZE: usize = HEADER_SIZE + PAYLOAD_SIZE + 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlobError {
+    // Fewer bytes than `CALIBRATION_BLOB_SIZE`
+    Length,
+    // Not a calibration blob, e.g. erased flash
+    Magic,
+    // Layout version other than `CALIBRATION_VERSION`, older or newer
+    Version(u8),
+    // CRC does not match the content
+    Checksum,
+}
+
+// Everything produced by the calibration routines, in a form that can be kept
+// in flash and re-applied at boot.
+//
+// The blob is little endian: a header of magic "IC", version, device id and
+// flags, followed by the offsets, accel bias and matrix, gyro matrix and the
+// thermal model, terminated by a CRC-32 (IEEE) over all preceding bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CalibrationData {
+    // `WhoAmI::device_id` of the calibrated device
+    pub device_id: u8,
+    pub gyro_offset: GyroOffset,
+    pub accel_offset: AccelOffset,
+    // Software accelerometer bias, scale and misalignment
+    pub accel: AccelCalibration,
+    // Software gyroscope scale and misalignment, applied to º/s
+    pub gyro_matrix: [[f32; 3]; 3],
+    pub gyro_thermal: Option<ThermalModel>,
+}
+impl Default for CalibrationData {
+    fn default() -> Self {
+        Self {
+            device_id: WhoAmI::ICM20608G,
+            gyro_offset: GyroOffset::default(),
+            accel_offset: AccelOffset::default(),
+            accel: AccelCalibration::default(),
+            gyro_matrix: IDENTITY,
+            gyro_thermal: None,
+        }
+    }
+}
+impl CalibrationData {
+    /// Captures the offsets currently programmed into the device, the software
+    /// corrections start out as identity
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn from_device<I: I2c>(i2c: &mut I) -> Result<Self, I::Error> {
+        Ok(Self {
+            device_id: WhoAmI::new(i2c)?.device_id,
+            gyro_offset: GyroOffset::new(i2c)?,
+            accel_offset: AccelOffset::new(i2c)?,
+            ..Self::default()
+        })
+    }
+
+    /// Writes the stored `GyroOffset` and `AccelOffset` to the device
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device, or with
+    /// `CalibrationError::DeviceMismatch` if the device reports a different
+    /// `WhoAmI` than the one the data was recorded on. Nothing is written in that case.
+    pub fn apply<I: I2c>(&self, i2c: &mut I) -> Result<(), CalibrationError<I::Error>> {
+        let device_id = WhoAmI::new(i2c).map_err(CalibrationError::I2c)?.device_id;
+        if device_id != self.device_id {
+            return Err(CalibrationError::DeviceMismatch);
+        }
+        self.gyro_offset.write(i2c).map_err(CalibrationError::I2c)?;
+        self.accel_offset.write(i2c).map_err(CalibrationError::I2c)
+    }
+
+    /// Converts a gyroscope reading in º/s with the software gyroscope matrix
+    #[must_use]
+    pub fn correct_gyro(&self, gyro: Vec3<f32>) -> Vec3<f32> {
+        let [x, y, z] = super::mul_vec(&self.gyro_matrix, [gyro.x, gyro.y, gyro.z]);
+        Vec3 { x, y, z }
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; CALIBRATION_BLOB_SIZE] {
+        let mut writer = Writer {
+            bytes: [0; CALIBRATION_BLOB_SIZE],
+            position: 0,
+        };
+        writer.put(&MAGIC);
+        writer.put(&[
+            CALIBRATION_VERSION,
+            self.device_id,
+            if self.gyro_thermal.is_some() {
+                FLAG_THERMAL
+            } else {
+                0
+            },
+        ]);
+        for offset in [
+            self.gyro_offset.xg_offs,
+            self.gyro_offset.yg_offs,
+            self.gyro_offset.zg_offs,
+            self.accel_offset.x_offs,
+            self.accel_offset.y_offs,
+            self.accel_offset.z_offs,
+        ] {
+            writer.put(&offset.to_le_bytes());
+        }
+        let bias = self.accel.bias;
+        for value in [bias.x, bias.y, bias.z] {
+            writer.put(&value.to_le_bytes());
+        }
+        for value in self.accel.matrix.iter().chain(&self.gyro_matrix).flatten() {
+            writer.put(&value.to_le_bytes());
+        }
+        let thermal = self.gyro_thermal.unwrap_or_default();
+        writer.put(&thermal.reference.to_le_bytes());
+        for value in thermal.coefficients.iter().flatten() {
+            writer.put(&value.to_le_bytes());
+        }
+        let crc = crc32(&writer.bytes[..writer.position]);
+        writer.put(&crc.to_le_bytes());
+        writer.bytes
+    }
+
+    /// Parses a blob written by `to_bytes`. Trailing bytes are ignored.
+    ///
+    /// # Errors
+    /// Will error if the bytes are not a valid calibration blob of a supported version
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BlobError> {
+        let bytes = bytes
+            .get(..CALIBRATION_BLOB_SIZE)
+            .ok_or(BlobError::Length)?;
+        if bytes[..2] != MAGIC {
+            return Err(BlobError::Magic);
+        }
+        // Checked before the version so a corrupted header reports a bad checksum
+        let (content, crc) = bytes.split_at(CALIBRATION_BLOB_SIZE - 4);
+        if crc32(content).to_le_bytes() != crc {
+            return Err(BlobError::Checksum);
+        }
+        if bytes[2] != CALIBRATION_VERSION {
+            return Err(BlobError::Version(bytes[2]));
+        }
+
+        let mut reader = Reader {
+            bytes: content,
+            position: 2,
+        };
+        let [_, device_id, flags] = reader.take();
+        let mut offset = || i16::from_le_bytes(reader.take());
+        let gyro_offset = GyroOffset {
+            xg_offs: offset(),
+            yg_offs: offset(),
+            zg_offs: offset(),
+        };
+        let accel_offset = AccelOffset {
+            x_offs: offset(),
+            y_offs: offset(),
+            z_offs: offset(),
+        };
+        let mut value = || f32::from_le_bytes(reader.take());
+        let bias = Vec3 {
+            x: value(),
+            y: value(),
+            z: value(),
+        };
+        let mut matrix = || [(); 3].map(|()| [(); 3].map(|()| value()));
+        let accel_matrix = matrix();
+        let gyro_matrix = matrix();
+        let reference = value();
+        let coefficients = [(); 3].map(|()| [(); MAX_THERMAL_DEGREE + 1].map(|()| value()));
+        Ok(Self {
+            device_id,
+            gyro_offset,
+            accel_offset,
+            accel: AccelCalibration {
+                bias,
+                matrix: accel_matrix,
+            },
+            gyro_matrix,
+            gyro_thermal: (flags & FLAG_THERMAL != 0).then_some(ThermalModel {
+                reference,
+                coefficients,
+            }),
+        })
+    }
+}
+
+struct Writer {
+    bytes: [u8; CALIBRATION_BLOB_SIZE],
+    position: usize,
+}
+impl Writer {
+    fn put(&mut self, data: &[u8]) {
+        self.bytes[self.position..self.position + data.len()].copy_from_slice(data);
+        self.position += data.len();
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let mut data = [0; N];
+        data.copy_from_slice(&self.bytes[self.position..self.position + N]);
+        self.position += N;
+        data
+    }
+}
+
+// CRC-32 (IEEE 802.3), bitwise to avoid a lookup table
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeDevice;
+
+    fn data(gyro_thermal: Option<ThermalModel>) -> CalibrationData {
+        CalibrationData {
+            device_id: WhoAmI::ICM20608G,
+            gyro_offset: GyroOffset {
+                xg_offs: 12,
+                yg_offs: -34,
+                zg_offs: 5_600,
+            },
+            accel_offset: AccelOffset {
+                x_offs: -7_000,
+                y_offs: 250,
+                z_offs: 16_000,
+            },
+            accel: AccelCalibration {
+                bias: Vec3::new(0.01, -0.02, 0.03),
+                matrix: [
+                    [1.01, 0.002, -0.003],
+                    [0.004, 0.99, 0.005],
+                    [-0.006, 0.007, 1.02],
+                ],
+            },
+            gyro_matrix: [[0.98, -0.01, 0.0], [0.01, 1.0, 0.02], [0.0, -0.02, 1.03]],
+            gyro_thermal,
+        }
+    }
+
+    // Rewrites the CRC after the content was modified
+    fn reseal(bytes: &mut [u8; CALIBRATION_BLOB_SIZE]) {
+        let crc = crc32(&bytes[..CALIBRATION_BLOB_SIZE - 4]);
+        bytes[CALIBRATION_BLOB_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn round_trips_with_and_without_thermal_model() {
+        let thermal = ThermalModel {
+            reference: 31.5,
+            coefficients: [
+                [0.5, 0.02, -0.001, 0.000_01],
+                [-0.3, 0.01, 0.0, 0.0],
+                [0.1, 0.0, 0.0, -0.000_2],
+            ],
+        };
+        for gyro_thermal in [None, Some(thermal)] {
+            let data = data(gyro_thermal);
+            let mut bytes = [0; CALIBRATION_BLOB_SIZE + 3];
+            bytes[..CALIBRATION_BLOB_SIZE].copy_from_slice(&data.to_bytes());
+            assert_eq!(CalibrationData::from_bytes(&bytes), Ok(data));
+        }
+    }
+
+    #[test]
+    fn rejects_damaged_blobs() {
+        let bytes = data(None).to_bytes();
+        assert_eq!(
+            CalibrationData::from_bytes(&bytes[..CALIBRATION_BLOB_SIZE - 1]),
+            Err(BlobError::Length)
+        );
+        assert_eq!(
+            CalibrationData::from_bytes(&[0xFF; CALIBRATION_BLOB_SIZE]),
+            Err(BlobError::Magic)
+        );
+        for (index, bit) in [(2, 0), (3, 7), (10, 3), (CALIBRATION_BLOB_SIZE - 1, 5)] {
+            let mut flipped = bytes;
+            flipped[index] ^= 1 << bit;
+            assert_eq!(
+                CalibrationData::from_bytes(&flipped),
+                Err(BlobError::Checksum),
+                "byte {index} bit {bit}"
+            );
+        }
+        for version in [0, CALIBRATION_VERSION + 1] {
+            let mut other = bytes;
+            other[2] = version;
+            reseal(&mut other);
+            assert_eq!(
+                CalibrationData::from_bytes(&other),
+                Err(BlobError::Version(version))
+            );
+        }
+    }
+
+    #[test]
+    fn apply_checks_the_device() {
+        let data = data(None);
+        let mut device = FakeDevice::new();
+        device.registers[0x75] = 0x12;
+        assert!(matches!(
+            data.apply(&mut device),
+            Err(CalibrationError::DeviceMismatch)
+        ));
+        assert_eq!(GyroOffset::new(&mut device).unwrap(), GyroOffset::default());
+        assert_eq!(
+            AccelOffset::new(&mut device).unwrap(),
+            AccelOffset::default()
+        );
+
+        device.registers[0x75] = WhoAmI::ICM20608G;
+        data.apply(&mut device).unwrap();
+        assert_eq!(GyroOffset::new(&mut device).unwrap(), data.gyro_offset);
+        assert_eq!(AccelOffset::new(&mut device).unwrap(), data.accel_offset);
+    }
+}
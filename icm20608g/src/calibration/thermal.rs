@@ -0,0 +1,314 @@
+use embedded_hal::i2c::I2c;
+
+use super::solve_linear;
+use crate::structs::{
+    GyroConfig, GyroOffset, GyroscopeMeasurements, TemperatureMeasurements, Vec3, WriteRegister,
+};
+
+// Highest supported polynomial degree
+pub const MAX_THERMAL_DEGREE: usize = 3;
+
+// Gyroscope bias as a polynomial of die temperature, one per axis:
+// bias(t) = c0 + c1 * (t - reference) + c2 * (t - reference)^2 + c3 * (t - reference)^3
+// Bias is in º/s, temperatures in ºC.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThermalModel {
+    // Temperature the polynomials are centered on
+    pub reference: f32,
+    // Coefficients per axis, lowest order first
+    pub coefficients: [[f32; MAX_THERMAL_DEGREE + 1]; 3],
+}
+impl ThermalModel {
+    /// Bias in º/s predicted at `celsius`
+    #[must_use]
+    pub fn bias(&self, celsius: f32) -> Vec3<f32> {
+        let dt = celsius - self.reference;
+        let [x, y, z] = self.coefficients.map(|coefficients| {
+            coefficients
+                .iter()
+                .rev()
+                .fold(0.0, |value, coefficient| value * dt + coefficient)
+        });
+        Vec3 { x, y, z }
+    }
+
+    /// Converts a gyroscope measurement to º/s and removes the bias predicted for
+    /// the current die temperature
+    #[must_use]
+    pub fn correct(
+        &self,
+        gyro: &GyroscopeMeasurements,
+        config: &GyroConfig,
+        temperature: &TemperatureMeasurements,
+    ) -> Vec3<f32> {
+        let rate = gyro.to_dps(config);
+        let bias = self.bias(temperature.celsius());
+        Vec3 {
+            x: rate.x - bias.x,
+            y: rate.y - bias.y,
+            z: rate.z - bias.z,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct TemperatureBin {
+    center: f32,
+    count: u32,
+    temperature: f32,
+    bias: [f32; 3],
+}
+
+// Records gyroscope bias over a temperature sweep.
+//
+// The device must be kept still while it heats up or cools down. Every sample is
+// sorted into a bin `bin_width` ºC wide and averaged, so the fit is not skewed
+// towards temperatures where the sweep lingered. Up to `N` bins are kept.
+pub struct ThermalCalibration<const N: usize> {
+    bin_width: f32,
+    bins: [TemperatureBin; N],
+    len: usize,
+}
+impl<const N: usize> ThermalCalibration<N> {
+    /// Returns None unless `bin_width` is a positive, finite number of ºC
+    #[must_use]
+    pub fn new(bin_width: f32) -> Option<Self> {
+        (bin_width.is_finite() && bin_width > 0.0).then(|| Self {
+            bin_width,
+            bins: [TemperatureBin::default(); N],
+            len: 0,
+        })
+    }
+
+    /// Number of temperature bins that received samples
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Temperature range covered so far, None before the first sample
+    #[must_use]
+    pub fn range(&self) -> Option<(f32, f32)> {
+        let bins = &self.bins[..self.len];
+        let min = bins.iter().map(|bin| bin.temperature).reduce(f32::min)?;
+        let max = bins.iter().map(|bin| bin.temperature).reduce(f32::max)?;
+        Some((min, max))
+    }
+
+    /// Records a stationary gyroscope sample in º/s taken at `celsius`.
+    /// Returns false if the sample falls into a new bin while all bins are in use.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn push(&mut self, celsius: f32, gyro: Vec3<f32>) -> bool {
+        let center = libm::roundf(celsius / self.bin_width) * self.bin_width;
+        let half_width = self.bin_width / 2.0;
+        let index = match self.bins[..self.len]
+            .iter()
+            .position(|bin| (bin.center - center).abs() < half_width)
+        {
+            Some(index) => index,
+            None if self.len < N => {
+                self.bins[self.len] = TemperatureBin {
+                    center,
+                    ..TemperatureBin::default()
+                };
+                self.len += 1;
+                self.len - 1
+            }
+            None => return false,
+        };
+        let bin = &mut self.bins[index];
+        bin.count += 1;
+        let count = bin.count as f32;
+        bin.temperature += (celsius - bin.temperature) / count;
+        for (bias, value) in bin.bias.iter_mut().zip([gyro.x, gyro.y, gyro.z]) {
+            *bias += (value - *bias) / count;
+        }
+        true
+    }
+
+    /// Fits a polynomial of `degree` per axis to the recorded bins. Returns None if
+    /// `degree` exceeds `MAX_THERMAL_DEGREE` or there are not more bins than the degree.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    #[must_use]
+    pub fn fit(&self, degree: usize) -> Option<ThermalModel> {
+        let bins = &self.bins[..self.len];
+        if degree > MAX_THERMAL_DEGREE || bins.len() <= degree {
+            return None;
+        }
+        let reference = bins.iter().map(|bin| bin.temperature).sum::<f32>() / bins.len() as f32;
+
+        let mut normal = [[0.0_f64; MAX_THERMAL_DEGREE + 1]; MAX_THERMAL_DEGREE + 1];
+        let mut rhs = [[0.0_f64; MAX_THERMAL_DEGREE + 1]; 3];
+        for bin in bins {
+            let dt = f64::from(bin.temperature - reference);
+            let mut powers = [1.0; MAX_THERMAL_DEGREE + 1];
+            for i in 1..=degree {
+                powers[i] = powers[i - 1] * dt;
+            }
+            for i in 0..=degree {
+                for j in 0..=degree {
+                    normal[i][j] += powers[i] * powers[j];
+                }
+                for (axis_rhs, bias) in rhs.iter_mut().zip(bin.bias) {
+                    axis_rhs[i] += powers[i] * f64::from(bias);
+                }
+            }
+        }
+        // Pin the unused higher order coefficients to zero
+        for (i, row) in normal.iter_mut().enumerate().skip(degree + 1) {
+            row[i] = 1.0;
+        }
+
+        let mut coefficients = [[0.0; MAX_THERMAL_DEGREE + 1]; 3];
+        for (axis, axis_rhs) in coefficients.iter_mut().zip(rhs) {
+            *axis = solve_linear(normal, axis_rhs)?.map(|value| value as f32);
+        }
+        Some(ThermalModel {
+            reference,
+            coefficients,
+        })
+    }
+}
+
+// Keeps `GyroOffset` matched to the die temperature so the device itself outputs
+// bias free data. The offsets are rewritten whenever the temperature moved by more
+// than `step` ºC since the last update.
+pub struct ThermalOffsetUpdater {
+    model: ThermalModel,
+    // Offsets that were in place while the model was recorded
+    base: GyroOffset,
+    step: f32,
+    last_temperature: Option<f32>,
+}
+impl ThermalOffsetUpdater {
+    #[must_use]
+    pub const fn new(model: ThermalModel, base: GyroOffset, step: f32) -> Self {
+        Self {
+            model,
+            base,
+            step,
+            last_temperature: None,
+        }
+    }
+
+    /// Offsets that cancel the modelled bias at `celsius`
+    #[must_use]
+    pub fn offset(&self, celsius: f32) -> GyroOffset {
+        self.base.compensate(self.model.bias(celsius))
+    }
+
+    /// Writes new offsets if the temperature changed enough and returns them
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn update<I: I2c>(
+        &mut self,
+        i2c: &mut I,
+        temperature: &TemperatureMeasurements,
+    ) -> Result<Option<GyroOffset>, I::Error> {
+        let celsius = temperature.celsius();
+        if self
+            .last_temperature
+            .is_some_and(|last| (celsius - last).abs() < self.step)
+        {
+            return Ok(None);
+        }
+        let offset = self.offset(celsius);
+        offset.write(i2c)?;
+        self.last_temperature = Some(celsius);
+        Ok(Some(offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::ReadRegister;
+    use crate::test_support::FakeDevice;
+
+    fn true_bias(celsius: f32) -> Vec3<f32> {
+        let dt = celsius - 30.0;
+        Vec3::new(0.5 + 0.02 * dt - 0.001 * dt * dt, -0.3 + 0.01 * dt, 0.1)
+    }
+
+    #[test]
+    fn fits_bias_over_temperature() {
+        let mut calibration = ThermalCalibration::<16>::new(2.0).unwrap();
+        for step in 0_u16..400 {
+            let celsius = 20.0 + f32::from(step) * 0.05;
+            assert!(calibration.push(celsius, true_bias(celsius)));
+        }
+        assert_eq!(calibration.len(), 11);
+        assert!(calibration.fit(MAX_THERMAL_DEGREE).is_some());
+        assert!(calibration.fit(MAX_THERMAL_DEGREE + 1).is_none());
+        let model = calibration.fit(2).unwrap();
+        for celsius in [21.0, 27.5, 34.0, 39.0] {
+            let error = model.bias(celsius) - true_bias(celsius);
+            assert!(error.norm() < 1e-3, "{celsius}: {error:?}");
+        }
+        // A straight line cannot follow the curvature on X
+        let linear = calibration.fit(1).unwrap();
+        assert!((linear.bias(20.0) - true_bias(20.0)).x.abs() > 0.01);
+    }
+
+    #[test]
+    fn rejects_bin_width_that_is_not_positive() {
+        assert!(ThermalCalibration::<4>::new(0.0).is_none());
+        assert!(ThermalCalibration::<4>::new(-1.0).is_none());
+        assert!(ThermalCalibration::<4>::new(f32::NAN).is_none());
+    }
+
+    #[test]
+    fn needs_more_bins_than_degree() {
+        let mut calibration = ThermalCalibration::<2>::new(1.0).unwrap();
+        assert!(calibration.fit(0).is_none());
+        assert!(calibration.push(25.0, true_bias(25.0)));
+        assert!(calibration.push(26.0, true_bias(26.0)));
+        assert!(!calibration.push(27.0, true_bias(27.0)));
+        assert_eq!(calibration.range(), Some((25.0, 26.0)));
+        assert!(calibration.fit(1).is_some());
+        assert!(calibration.fit(2).is_none());
+    }
+
+    #[test]
+    fn updater_rewrites_offsets_after_temperature_step() {
+        let model = ThermalModel {
+            reference: 25.0,
+            coefficients: [[0.0, 0.1, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0], [0.0; 4]],
+        };
+        let base = GyroOffset {
+            xg_offs: 10,
+            yg_offs: -20,
+            zg_offs: 0,
+        };
+        let mut updater = ThermalOffsetUpdater::new(model, base, 0.5);
+        let mut device = FakeDevice::new();
+        // 25ºC and 25.3ºC, then 26ºC
+        let temperature = |temp_out| TemperatureMeasurements { temp_out };
+
+        let offset = updater
+            .update(&mut device, &temperature(0))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (offset.xg_offs, offset.yg_offs, offset.zg_offs),
+            (10, -53, 0)
+        );
+        assert!(updater
+            .update(&mut device, &temperature(98))
+            .unwrap()
+            .is_none());
+        let offset = updater
+            .update(&mut device, &temperature(327))
+            .unwrap()
+            .unwrap();
+        assert_eq!(offset.xg_offs, 7);
+        assert_eq!(GyroOffset::new(&mut device).unwrap().xg_offs, 7);
+    }
+}
@@ -0,0 +1,568 @@
+use embedded_hal::i2c::I2c;
+
+use crate::fsync::FsyncSource;
+use crate::structs::{
+    AccelMeasurements, Config, FifoCountRegisters, FifoEnable, FifoReadWrite,
+    GyroscopeMeasurements, InterruptStatus, ReadRegister, TemperatureMeasurements, UserControl,
+    WriteRegister, IMU_ADDR,
+};
+
+mod timestamp;
+pub use timestamp::FifoTimestamper;
+
+// Size of the on-chip FIFO in bytes
+pub const FIFO_SIZE: usize = 512;
+
+// Selects which measurements are written to the FIFO at the sample rate.
+// Data is written in register order: accel X/Y/Z, temperature, gyro X/Y/Z,
+// each as a big endian 16 bit word.
+// `fsync` is the register configured through `fsync::set_source`, its tag bit is
+// stripped while decoding and reported in `FifoFrame::fsync`.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FifoLayout {
+    pub accel: bool,
+    pub temperature: bool,
+    pub gyro_x: bool,
+    pub gyro_y: bool,
+    pub gyro_z: bool,
+    pub fsync: Option<FsyncSource>,
+}
+impl FifoLayout {
+    /// Number of bytes the device writes into the FIFO for every sample
+    #[must_use]
+    pub const fn frame_size(&self) -> usize {
+        let mut size = 0;
+        if self.accel {
+            size += 6;
+        }
+        if self.temperature {
+            size += 2;
+        }
+        if self.gyro_x {
+            size += 2;
+        }
+        if self.gyro_y {
+            size += 2;
+        }
+        if self.gyro_z {
+            size += 2;
+        }
+        size
+    }
+
+    /// Number of whole frames that fit into the FIFO
+    #[must_use]
+    pub const fn capacity_frames(&self) -> usize {
+        match self.frame_size() {
+            0 => 0,
+            frame_size => FIFO_SIZE / frame_size,
+        }
+    }
+
+    /// Decodes a single frame. `bytes` must hold at least `frame_size` bytes.
+    #[must_use]
+    pub fn decode(&self, bytes: &[u8]) -> FifoFrame {
+        let mut words = bytes
+            .chunks_exact(2)
+            .map(|word| i16::from_be_bytes([word[0], word[1]]));
+        let mut next = || words.next().unwrap_or_default();
+
+        let accel = self.accel.then(|| AccelMeasurements {
+            x: next(),
+            y: next(),
+            z: next(),
+        });
+        let temperature = self
+            .temperature
+            .then(|| TemperatureMeasurements { temp_out: next() });
+        let gyro = (self.gyro_x || self.gyro_y || self.gyro_z).then(|| GyroscopeMeasurements {
+            x: if self.gyro_x { next() } else { 0 },
+            y: if self.gyro_y { next() } else { 0 },
+            z: if self.gyro_z { next() } else { 0 },
+        });
+        let mut frame = FifoFrame {
+            accel,
+            temperature,
+            gyro,
+            fsync: false,
+        };
+        if let Some(source) = self.fsync {
+            source.strip_frame(&mut frame);
+        }
+        frame
+    }
+
+    fn fifo_enable(self) -> FifoEnable {
+        FifoEnable {
+            temp_fifo_en: self.temperature,
+            xg_fifo_en: self.gyro_x,
+            yg_fifo_en: self.gyro_y,
+            zg_fifo_en: self.gyro_z,
+            accel_fifo_en: self.accel,
+        }
+    }
+}
+impl From<&FifoEnable> for FifoLayout {
+    fn from(value: &FifoEnable) -> Self {
+        Self {
+            accel: value.accel_fifo_en,
+            temperature: value.temp_fifo_en,
+            gyro_x: value.xg_fifo_en,
+            gyro_y: value.yg_fifo_en,
+            gyro_z: value.zg_fifo_en,
+            fsync: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FifoMode {
+    // When the FIFO is full additional writes overwrite the oldest data.
+    // The host is expected to drain it often enough that this never happens.
+    Stream,
+    // When the FIFO is full additional writes are dropped, the FIFO then holds
+    // a contiguous capture starting at the moment it was armed.
+    Snapshot,
+}
+
+// A single sample read back from the FIFO.
+// Measurements that are not part of the layout are None, gyro axes that are
+// not enabled read as 0. `fsync` is set when the sample coincided with an FSYNC edge.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FifoFrame {
+    pub accel: Option<AccelMeasurements>,
+    pub temperature: Option<TemperatureMeasurements>,
+    pub gyro: Option<GyroscopeMeasurements>,
+    pub fsync: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FifoBatch {
+    // Number of frames decoded into the output buffer
+    pub frames: usize,
+    // Whole frames left in the FIFO because they did not fit into the output buffer
+    pub remaining: usize,
+    // Set when an overflow was detected and the FIFO was reset
+    pub overflowed: bool,
+    // Whole frames that were in the FIFO when it was reset after an overflow.
+    // This is a lower bound, samples the device dropped or overwrote before the
+    // overflow was noticed cannot be counted.
+    pub lost_frames: usize,
+    // Interrupt status read at the start of the batch. Reading it clears the
+    // bits on the device, so the other flags are handed back to the caller here.
+    pub interrupts: InterruptStatus,
+}
+
+pub struct FifoReader {
+    layout: FifoLayout,
+}
+impl FifoReader {
+    #[must_use]
+    pub const fn new(layout: FifoLayout) -> Self {
+        Self { layout }
+    }
+
+    #[must_use]
+    pub const fn layout(&self) -> FifoLayout {
+        self.layout
+    }
+
+    /// Selects what happens when the FIFO is full by updating `Config::fifo_mode`
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn set_mode<I: I2c>(&self, i2c: &mut I, mode: FifoMode) -> Result<(), I::Error> {
+        let mut config = Config::new(i2c)?;
+        config.fifo_mode = mode == FifoMode::Snapshot;
+        config.write(i2c)
+    }
+
+    /// Stops writes to the FIFO, resets it and enables it again with the reader's layout.
+    /// After this the first byte in the FIFO is the start of a frame.
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn reset<I: I2c>(&self, i2c: &mut I) -> Result<(), I::Error> {
+        FifoLayout::default().fifo_enable().write(i2c)?;
+        let mut user_control = UserControl::new(i2c)?;
+        user_control.sig_cond_rst = false;
+        user_control.fifo_en = false;
+        user_control.fifo_rst = true;
+        user_control.write(i2c)?;
+        user_control.fifo_en = true;
+        user_control.fifo_rst = false;
+        user_control.write(i2c)?;
+        self.layout.fifo_enable().write(i2c)
+    }
+
+    /// Reads as many whole frames as are available and fit into `out`.
+    ///
+    /// If the FIFO overflowed, either signalled by `InterruptStatus::fifo_oflow_int`
+    /// or by a full FIFO that no longer ends on a frame boundary, the frame alignment
+    /// can no longer be trusted. The FIFO is then reset and re-armed, no frames are
+    /// returned and the discarded frames are reported in `FifoBatch::lost_frames`.
+    ///
+    /// Reading `InterruptStatus` clears all of its bits, the status that was read is
+    /// returned in `FifoBatch::interrupts` so that other flags are not lost.
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn read<I: I2c>(
+        &mut self,
+        i2c: &mut I,
+        out: &mut [FifoFrame],
+    ) -> Result<FifoBatch, I::Error> {
+        let frame_size = self.layout.frame_size();
+        if frame_size == 0 {
+            return Ok(FifoBatch::default());
+        }
+
+        let interrupts = InterruptStatus::new(i2c)?;
+        let count = usize::from(FifoCountRegisters::new(i2c)?.fifo_count);
+        // A full FIFO is only suspicious when the frame size does not divide it,
+        // otherwise it may simply hold a whole number of frames.
+        let misaligned = count >= FIFO_SIZE && count % frame_size != 0;
+        if interrupts.fifo_oflow_int || misaligned {
+            self.reset(i2c)?;
+            return Ok(FifoBatch {
+                frames: 0,
+                overflowed: true,
+                lost_frames: count.min(FIFO_SIZE) / frame_size,
+                interrupts,
+                ..FifoBatch::default()
+            });
+        }
+
+        let frames = self.drain(i2c, count, out)?;
+        Ok(FifoBatch {
+            frames,
+            remaining: count.min(FIFO_SIZE) / frame_size - frames,
+            interrupts,
+            ..FifoBatch::default()
+        })
+    }
+
+    // Reads and decodes the whole frames out of `count` bytes that fit into `out`
+    fn drain<I: I2c>(
+        &self,
+        i2c: &mut I,
+        count: usize,
+        out: &mut [FifoFrame],
+    ) -> Result<usize, I::Error> {
+        let frame_size = self.layout.frame_size();
+        let frames = out.len().min(count.min(FIFO_SIZE) / frame_size);
+        let mut read_buf = [0; FIFO_SIZE];
+        let read_buf = &mut read_buf[..frames * frame_size];
+        if frames > 0 {
+            i2c.write_read(IMU_ADDR, &[FifoReadWrite::ADDRESS], read_buf)?;
+        }
+        for (frame, bytes) in out.iter_mut().zip(read_buf.chunks_exact(frame_size)) {
+            *frame = self.layout.decode(bytes);
+        }
+        Ok(frames)
+    }
+}
+
+// Continuous capture. The FIFO runs in `FifoMode::Stream` and is drained
+// whenever `poll` is called after the drain period has elapsed.
+pub struct FifoStream {
+    reader: FifoReader,
+    // Time between drains in microseconds
+    period_us: u64,
+    // Host time in microseconds at which the next drain is due
+    next_drain_us: u64,
+}
+impl FifoStream {
+    /// Creates a stream that drains the FIFO `drain_rate_hz` times per second.
+    /// The rate should be high enough that the FIFO does not fill up between drains,
+    /// see `FifoLayout::capacity_frames`.
+    #[must_use]
+    pub fn new(layout: FifoLayout, drain_rate_hz: u32) -> Self {
+        Self {
+            reader: FifoReader::new(layout),
+            period_us: 1_000_000 / u64::from(drain_rate_hz.max(1)),
+            next_drain_us: 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn reader(&self) -> &FifoReader {
+        &self.reader
+    }
+
+    /// Switches the FIFO to stream mode and resets it. `now_us` is the current host time.
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn start<I: I2c>(&mut self, i2c: &mut I, now_us: u64) -> Result<(), I::Error> {
+        self.reader.set_mode(i2c, FifoMode::Stream)?;
+        self.reader.reset(i2c)?;
+        self.next_drain_us = now_us + self.period_us;
+        Ok(())
+    }
+
+    /// Drains the FIFO into `out` if a drain is due, otherwise returns None.
+    /// Overflows are handled as in `FifoReader::read`.
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn poll<I: I2c>(
+        &mut self,
+        i2c: &mut I,
+        now_us: u64,
+        out: &mut [FifoFrame],
+    ) -> Result<Option<FifoBatch>, I::Error> {
+        if now_us < self.next_drain_us {
+            return Ok(None);
+        }
+        // Skip drains that were missed instead of bursting to catch up
+        self.next_drain_us = if now_us >= self.next_drain_us + self.period_us {
+            now_us + self.period_us
+        } else {
+            self.next_drain_us + self.period_us
+        };
+        self.reader.read(i2c, out).map(Some)
+    }
+}
+
+// One-shot capture. The FIFO runs in `FifoMode::Snapshot`, fills up after being
+// armed and is then read out in one go.
+pub struct FifoSnapshot {
+    reader: FifoReader,
+}
+impl FifoSnapshot {
+    #[must_use]
+    pub const fn new(layout: FifoLayout) -> Self {
+        Self {
+            reader: FifoReader::new(layout),
+        }
+    }
+
+    #[must_use]
+    pub const fn reader(&self) -> &FifoReader {
+        &self.reader
+    }
+
+    /// Switches the FIFO to snapshot mode and resets it, starting a new capture
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn arm<I: I2c>(&mut self, i2c: &mut I) -> Result<(), I::Error> {
+        self.reader.set_mode(i2c, FifoMode::Snapshot)?;
+        self.reader.reset(i2c)
+    }
+
+    /// Returns None while the capture is still filling. Once the FIFO has no room
+    /// for another frame the whole capture is decoded into `out` and the number of
+    /// frames is returned. `out` should hold `FifoLayout::capacity_frames` frames,
+    /// anything that does not fit is discarded when the snapshot is re-armed.
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn poll<I: I2c>(
+        &mut self,
+        i2c: &mut I,
+        out: &mut [FifoFrame],
+    ) -> Result<Option<usize>, I::Error> {
+        let frame_size = self.reader.layout.frame_size();
+        if frame_size == 0 {
+            return Ok(Some(0));
+        }
+        let count = usize::from(FifoCountRegisters::new(i2c)?.fifo_count);
+        if count + frame_size <= FIFO_SIZE {
+            return Ok(None);
+        }
+        self.reader.drain(i2c, count, out).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeDevice;
+
+    const ACCEL_ONLY: FifoLayout = FifoLayout {
+        accel: true,
+        temperature: false,
+        gyro_x: false,
+        gyro_y: false,
+        gyro_z: false,
+        fsync: None,
+    };
+
+    fn accel_frame(x: i16, y: i16, z: i16) -> [u8; 6] {
+        let mut bytes = [0; 6];
+        bytes[0..2].copy_from_slice(&x.to_be_bytes());
+        bytes[2..4].copy_from_slice(&y.to_be_bytes());
+        bytes[4..6].copy_from_slice(&z.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn overflow_resets_and_reports_lost_frames() {
+        let mut device = FakeDevice::new();
+        device.push_fifo(&[0x55; 300]);
+        // FIFO overflow together with data ready
+        device.registers[0x3A] = (1 << 4) | 1;
+
+        let mut reader = FifoReader::new(ACCEL_ONLY);
+        let mut out = [FifoFrame::default(); 8];
+        let batch = reader.read(&mut device, &mut out).unwrap();
+        assert!(batch.overflowed);
+        assert_eq!(batch.frames, 0);
+        assert_eq!(batch.lost_frames, 50);
+        assert!(batch.interrupts.data_rdy_int);
+
+        // Emptied and re-armed with the reader's layout
+        assert_eq!(FifoCountRegisters::new(&mut device).unwrap().fifo_count, 0);
+        assert!(UserControl::new(&mut device).unwrap().fifo_en);
+        assert_eq!(
+            FifoLayout::from(&FifoEnable::new(&mut device).unwrap()),
+            ACCEL_ONLY
+        );
+    }
+
+    #[test]
+    fn full_fifo_of_whole_frames_is_read() {
+        let layout = FifoLayout {
+            temperature: true,
+            ..ACCEL_ONLY
+        };
+        assert_eq!(FIFO_SIZE % layout.frame_size(), 0);
+        let mut device = FakeDevice::new();
+        device.push_fifo(&[0; FIFO_SIZE]);
+
+        let mut reader = FifoReader::new(layout);
+        let mut out = [FifoFrame::default(); 64];
+        let batch = reader.read(&mut device, &mut out).unwrap();
+        assert!(!batch.overflowed);
+        assert_eq!(batch.frames, 64);
+        assert_eq!(batch.remaining, 0);
+    }
+
+    #[test]
+    fn full_fifo_off_a_frame_boundary_is_an_overflow() {
+        let layout = FifoLayout {
+            temperature: true,
+            gyro_x: true,
+            gyro_y: true,
+            gyro_z: true,
+            ..ACCEL_ONLY
+        };
+        let mut device = FakeDevice::new();
+        device.push_fifo(&[0; FIFO_SIZE]);
+
+        let mut reader = FifoReader::new(layout);
+        let mut out = [FifoFrame::default(); 36];
+        let batch = reader.read(&mut device, &mut out).unwrap();
+        assert!(batch.overflowed);
+        assert_eq!(batch.lost_frames, FIFO_SIZE / 14);
+    }
+
+    #[test]
+    fn partial_frames_are_left_in_the_fifo() {
+        let mut device = FakeDevice::new();
+        device.push_fifo(&accel_frame(1, 2, 3));
+        device.push_fifo(&accel_frame(-4, -5, -6));
+        device.push_fifo(&accel_frame(7, 8, 9)[..3]);
+
+        let mut reader = FifoReader::new(ACCEL_ONLY);
+        let mut out = [FifoFrame::default(); 1];
+        let batch = reader.read(&mut device, &mut out).unwrap();
+        assert_eq!((batch.frames, batch.remaining), (1, 1));
+        assert_eq!(out[0].accel, Some(AccelMeasurements { x: 1, y: 2, z: 3 }));
+
+        let mut out = [FifoFrame::default(); 4];
+        let batch = reader.read(&mut device, &mut out).unwrap();
+        assert_eq!((batch.frames, batch.remaining), (1, 0));
+        assert_eq!(
+            out[0].accel,
+            Some(AccelMeasurements {
+                x: -4,
+                y: -5,
+                z: -6
+            })
+        );
+        // The partial frame waits for the rest of its bytes
+        assert_eq!(FifoCountRegisters::new(&mut device).unwrap().fifo_count, 3);
+    }
+
+    #[test]
+    fn stream_drains_once_per_period() {
+        let mut device = FakeDevice::new();
+        let mut stream = FifoStream::new(ACCEL_ONLY, 100);
+        let mut out = [FifoFrame::default(); 4];
+        stream.start(&mut device, 1_000).unwrap();
+
+        assert!(stream
+            .poll(&mut device, 10_999, &mut out)
+            .unwrap()
+            .is_none());
+        device.push_fifo(&accel_frame(1, 2, 3));
+        let batch = stream.poll(&mut device, 11_000, &mut out).unwrap().unwrap();
+        assert_eq!(batch.frames, 1);
+        assert!(stream
+            .poll(&mut device, 11_500, &mut out)
+            .unwrap()
+            .is_none());
+        // A late drain keeps the original schedule
+        assert!(stream
+            .poll(&mut device, 24_000, &mut out)
+            .unwrap()
+            .is_some());
+        assert!(stream
+            .poll(&mut device, 30_999, &mut out)
+            .unwrap()
+            .is_none());
+        assert!(stream
+            .poll(&mut device, 31_000, &mut out)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn stream_skips_missed_drains() {
+        let mut device = FakeDevice::new();
+        let mut stream = FifoStream::new(ACCEL_ONLY, 100);
+        let mut out = [FifoFrame::default(); 4];
+        stream.start(&mut device, 0).unwrap();
+
+        // Several periods late, the next drain is a full period away
+        assert!(stream
+            .poll(&mut device, 55_000, &mut out)
+            .unwrap()
+            .is_some());
+        assert!(stream
+            .poll(&mut device, 60_000, &mut out)
+            .unwrap()
+            .is_none());
+        assert!(stream
+            .poll(&mut device, 64_999, &mut out)
+            .unwrap()
+            .is_none());
+        assert!(stream
+            .poll(&mut device, 65_000, &mut out)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn snapshot_completes_when_no_frame_fits() {
+        let mut device = FakeDevice::new();
+        let mut snapshot = FifoSnapshot::new(ACCEL_ONLY);
+        let mut out = [FifoFrame::default(); 85];
+        snapshot.arm(&mut device).unwrap();
+        assert!(Config::new(&mut device).unwrap().fifo_mode);
+
+        // 84 frames leave room for one more
+        for _ in 0..84 {
+            device.push_fifo(&accel_frame(0, 0, 1));
+        }
+        assert_eq!(snapshot.poll(&mut device, &mut out).unwrap(), None);
+
+        device.push_fifo(&accel_frame(0, 0, 1));
+        assert_eq!(snapshot.poll(&mut device, &mut out).unwrap(), Some(85));
+        assert_eq!(out[84].accel, Some(AccelMeasurements { x: 0, y: 0, z: 1 }));
+    }
+}
@@ -0,0 +1,209 @@
+use super::FifoBatch;
+
+// How far the estimated sample period may drift from the nominal one.
+// The internal oscillator is specified to within a few percent.
+const MAX_PERIOD_DEVIATION: f64 = 0.05;
+
+// Assigns host timestamps to frames drained from the FIFO.
+//
+// The FIFO carries no time information, so every frame of a batch is placed on
+// a grid spaced by the sample period that ends near the drain time. The period
+// starts at the configured ODR and is continuously corrected against the host
+// clock, which absorbs the drift of the device's internal oscillator.
+pub struct FifoTimestamper {
+    nominal_period_us: f64,
+    // Current estimate of the sample period against the host clock
+    period_us: f64,
+    // Weight given to each new batch when updating the period and phase
+    gain: f64,
+    previous_drain_us: Option<u64>,
+    // Frames left in the FIFO by the previous drain
+    previous_remaining: usize,
+    last_sample_us: Option<f64>,
+}
+impl FifoTimestamper {
+    /// Creates a timestamper for the ODR the device was configured with,
+    /// see `SampleRateDivider::sample_rate_hz`.
+    #[must_use]
+    pub fn new(sample_rate_hz: f32) -> Self {
+        let nominal_period_us = 1_000_000.0 / f64::from(sample_rate_hz);
+        Self {
+            nominal_period_us,
+            period_us: nominal_period_us,
+            gain: 0.05,
+            previous_drain_us: None,
+            previous_remaining: 0,
+            last_sample_us: None,
+        }
+    }
+
+    /// Sets how fast drift is corrected, between 0 (never) and 1 (trust every batch fully).
+    #[must_use]
+    pub fn with_gain(mut self, gain: f32) -> Self {
+        self.gain = f64::from(gain.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Current estimate of the sample period in microseconds of host time
+    #[must_use]
+    pub const fn period_us(&self) -> f64 {
+        self.period_us
+    }
+
+    /// Forgets the time of the previous drain, the next batch is anchored to its
+    /// drain time again. The period estimate is kept.
+    pub fn reset(&mut self) {
+        self.previous_drain_us = None;
+        self.previous_remaining = 0;
+        self.last_sample_us = None;
+    }
+
+    /// Writes a timestamp in microseconds for every frame of `batch` into `out`,
+    /// `now_us` is the host time at which the batch was drained. Frames the batch
+    /// left in the FIFO are accounted for, they are timestamped with the next batch.
+    /// After an overflow the continuity to earlier batches is lost and the
+    /// timestamper starts over from `now_us`.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn timestamp(&mut self, now_us: u64, batch: &FifoBatch, out: &mut [u64]) {
+        if batch.overflowed {
+            self.reset();
+            self.previous_drain_us = Some(now_us);
+            return;
+        }
+        if batch.frames == 0 {
+            return;
+        }
+        let frames = batch.frames as f64;
+        let remaining = batch.remaining as f64;
+        let now = now_us as f64;
+
+        // Frames sampled since the previous drain, including those still in the FIFO
+        let produced = (batch.frames + batch.remaining).saturating_sub(self.previous_remaining);
+        if let Some(previous_drain_us) = self.previous_drain_us.filter(|_| produced > 0) {
+            let measured = (now - previous_drain_us as f64) / produced as f64;
+            let limit = self.nominal_period_us * MAX_PERIOD_DEVIATION;
+            self.period_us += self.gain * (measured - self.period_us);
+            self.period_us = self.period_us.clamp(
+                self.nominal_period_us - limit,
+                self.nominal_period_us + limit,
+            );
+        }
+
+        // The newest frame in the FIFO was sampled at some point during the last
+        // period before the drain, on average half a period earlier. The frames
+        // left in the FIFO are newer than the ones read.
+        let anchored_first = now - self.period_us * (frames + remaining - 0.5);
+        let first = match self.last_sample_us {
+            Some(last_sample_us) => {
+                let predicted = last_sample_us + self.period_us;
+                let corrected = predicted + self.gain * (anchored_first - predicted);
+                corrected.max(last_sample_us)
+            }
+            None => anchored_first,
+        };
+
+        for (i, timestamp) in out.iter_mut().take(batch.frames).enumerate() {
+            let time = first + self.period_us * i as f64;
+            *timestamp = (time.max(0.0) + 0.5) as u64;
+        }
+        self.last_sample_us = Some(first + self.period_us * (frames - 1.0));
+        self.previous_drain_us = Some(now_us);
+        self.previous_remaining = batch.remaining;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Device sampling with a period of `true_period_us`, drained every 10 ms into
+    // buffers of `capacity(drain)` frames. Returns the largest timestamp error
+    // in the second half of the run.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn simulate(
+        timestamper: &mut FifoTimestamper,
+        true_period_us: f64,
+        capacity: impl Fn(usize) -> usize,
+    ) -> f64 {
+        let mut read = 0_usize;
+        let mut max_error: f64 = 0.0;
+        let mut out = [0_u64; 32];
+        for drain in 1..=400 {
+            let now_us = drain as u64 * 10_000;
+            let sampled = (now_us as f64 / true_period_us) as usize;
+            let frames = capacity(drain).min(sampled - read);
+            let batch = FifoBatch {
+                frames,
+                remaining: sampled - read - frames,
+                ..FifoBatch::default()
+            };
+            timestamper.timestamp(now_us, &batch, &mut out);
+            for (i, &timestamp) in out[..frames].iter().enumerate() {
+                let truth = (read + i + 1) as f64 * true_period_us;
+                if drain > 200 {
+                    max_error = max_error.max((timestamp as f64 - truth).abs());
+                }
+            }
+            read += frames;
+        }
+        max_error
+    }
+
+    #[test]
+    fn tracks_oscillator_drift() {
+        let mut timestamper = FifoTimestamper::new(1000.0);
+        let error = simulate(&mut timestamper, 1020.0, |_| 32);
+        assert!(
+            (timestamper.period_us() - 1020.0).abs() < 15.0,
+            "period {}",
+            timestamper.period_us()
+        );
+        assert!(error < 1020.0, "error {error}");
+    }
+
+    #[test]
+    fn accounts_for_frames_left_in_the_fifo() {
+        let mut timestamper = FifoTimestamper::new(1000.0);
+        // Alternately reads fewer and more frames than were produced
+        let error = simulate(&mut timestamper, 1020.0, |drain| {
+            if drain % 2 == 0 {
+                6
+            } else {
+                14
+            }
+        });
+        assert!(
+            (timestamper.period_us() - 1020.0).abs() < 15.0,
+            "period {}",
+            timestamper.period_us()
+        );
+        assert!(error < 1020.0, "error {error}");
+    }
+
+    #[test]
+    fn overflow_restarts_from_drain_time() {
+        let mut timestamper = FifoTimestamper::new(1000.0);
+        let mut out = [0_u64; 4];
+        let overflow = FifoBatch {
+            overflowed: true,
+            lost_frames: 42,
+            ..FifoBatch::default()
+        };
+        timestamper.timestamp(5_000, &overflow, &mut out);
+        let batch = FifoBatch {
+            frames: 4,
+            ..FifoBatch::default()
+        };
+        timestamper.timestamp(9_000, &batch, &mut out);
+        assert!(out.windows(2).all(|pair| pair[1] > pair[0]));
+        assert!(out[3] <= 9_000 && out[3] > 8_000);
+    }
+}
@@ -0,0 +1,173 @@
+use core::f32::consts::{FRAC_1_SQRT_2, PI};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterKind {
+    LowPass,
+    HighPass,
+    // Rejects a narrow band around the frequency, `q` sets its width
+    Notch,
+}
+
+// Parameters a biquad is designed from, independent of the sample rate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterDesign {
+    pub kind: FilterKind,
+    // Cutoff or notch center frequency in Hz
+    pub frequency_hz: f32,
+    // Quality factor, `BUTTERWORTH_Q` gives a maximally flat low or high pass
+    pub q: f32,
+}
+impl FilterDesign {
+    pub const BUTTERWORTH_Q: f32 = FRAC_1_SQRT_2;
+
+    #[must_use]
+    pub const fn low_pass(frequency_hz: f32, q: f32) -> Self {
+        Self {
+            kind: FilterKind::LowPass,
+            frequency_hz,
+            q,
+        }
+    }
+
+    #[must_use]
+    pub const fn high_pass(frequency_hz: f32, q: f32) -> Self {
+        Self {
+            kind: FilterKind::HighPass,
+            frequency_hz,
+            q,
+        }
+    }
+
+    #[must_use]
+    pub const fn notch(frequency_hz: f32, q: f32) -> Self {
+        Self {
+            kind: FilterKind::Notch,
+            frequency_hz,
+            q,
+        }
+    }
+}
+
+// Coefficients of a second order section, normalized so that a0 is 1:
+// y[n] = b0 x[n] + b1 x[n-1] + b2 x[n-2] - a1 y[n-1] - a2 y[n-2]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Biquad {
+    pub b0: f32,
+    pub b1: f32,
+    pub b2: f32,
+    pub a1: f32,
+    pub a2: f32,
+}
+impl Biquad {
+    // Passes the input through unchanged
+    pub const IDENTITY: Self = Self {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    /// Designs the section with the bilinear transform formulas of the Audio EQ
+    /// Cookbook. None if the frequency is not between 0 and the Nyquist frequency
+    /// or `q` is not positive.
+    #[must_use]
+    pub fn design(design: &FilterDesign, sample_rate_hz: f32) -> Option<Self> {
+        let nyquist = sample_rate_hz / 2.0;
+        if !(design.frequency_hz > 0.0 && design.frequency_hz < nyquist && design.q > 0.0) {
+            return None;
+        }
+        let omega = 2.0 * PI * design.frequency_hz / sample_rate_hz;
+        let (sin, cos) = libm::sincosf(omega);
+        let alpha = sin / (2.0 * design.q);
+        let (b0, b1, b2) = match design.kind {
+            FilterKind::LowPass => {
+                let b0 = f32::midpoint(1.0, -cos);
+                (b0, 2.0 * b0, b0)
+            }
+            FilterKind::HighPass => {
+                let b0 = f32::midpoint(1.0, cos);
+                (b0, -2.0 * b0, b0)
+            }
+            FilterKind::Notch => (1.0, -2.0 * cos, 1.0),
+        };
+        let a0 = 1.0 + alpha;
+        Some(Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+        })
+    }
+
+    /// Gain for a constant input
+    #[must_use]
+    pub fn dc_gain(&self) -> f32 {
+        (self.b0 + self.b1 + self.b2) / (1.0 + self.a1 + self.a2)
+    }
+
+    // One sample through the transposed direct form II
+    pub(crate) fn step(&self, state: &mut [f32; 2], input: f32) -> f32 {
+        let output = self.b0 * input + state[0];
+        state[0] = self.b1 * input - self.a1 * output + state[1];
+        state[1] = self.b2 * input - self.a2 * output;
+        output
+    }
+
+    // State the section is in after a constant `input` for a long time
+    pub(crate) fn settled(&self, input: f32) -> [f32; 2] {
+        let output = self.dc_gain() * input;
+        let z2 = self.b2 * input - self.a2 * output;
+        [self.b1 * input - self.a1 * output + z2, z2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Amplitude of the steady state response to a sine at `frequency_hz`, from
+    // its RMS value over whole periods
+    #[allow(clippy::cast_precision_loss)]
+    fn gain(biquad: &Biquad, frequency_hz: f32, sample_rate_hz: f32) -> f32 {
+        let mut state = [0.0; 2];
+        let mut sum_squares = 0.0;
+        for n in 0..4000 {
+            let phase = 2.0 * PI * frequency_hz * n as f32 / sample_rate_hz;
+            let output = biquad.step(&mut state, libm::sinf(phase));
+            if n >= 2000 {
+                sum_squares += output * output;
+            }
+        }
+        libm::sqrtf(2.0 * sum_squares / 2000.0)
+    }
+
+    #[test]
+    fn matches_the_designed_response() {
+        let q = FilterDesign::BUTTERWORTH_Q;
+        let low_pass = Biquad::design(&FilterDesign::low_pass(50.0, q), 1000.0).unwrap();
+        assert!((low_pass.dc_gain() - 1.0).abs() < 1e-5);
+        assert!((gain(&low_pass, 50.0, 1000.0) - FRAC_1_SQRT_2).abs() < 0.01);
+        assert!(gain(&low_pass, 400.0, 1000.0) < 0.02);
+
+        let high_pass = Biquad::design(&FilterDesign::high_pass(50.0, q), 1000.0).unwrap();
+        assert!(high_pass.dc_gain().abs() < 1e-5);
+        assert!((gain(&high_pass, 50.0, 1000.0) - FRAC_1_SQRT_2).abs() < 0.01);
+
+        let notch = Biquad::design(&FilterDesign::notch(100.0, 5.0), 1000.0).unwrap();
+        assert!(gain(&notch, 100.0, 1000.0) < 0.01);
+        assert!((gain(&notch, 300.0, 1000.0) - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn settled_state_holds_a_constant_input() {
+        let biquad = Biquad::design(&FilterDesign::low_pass(20.0, 0.9), 500.0).unwrap();
+        let mut state = biquad.settled(2.5);
+        for _ in 0..10 {
+            assert!((biquad.step(&mut state, 2.5) - 2.5).abs() < 1e-5);
+        }
+        assert!(Biquad::design(&FilterDesign::low_pass(250.0, 0.7), 500.0).is_none());
+        assert!(Biquad::design(&FilterDesign::notch(20.0, -1.0), 500.0).is_none());
+    }
+}
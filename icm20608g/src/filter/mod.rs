@@ -0,0 +1,404 @@
+use crate::sample::ImuSample;
+use crate::structs::Vec3;
+
+mod biquad;
+pub use biquad::{Biquad, FilterDesign, FilterKind};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterError {
+    // All `N` stages of the bank are in use
+    Full,
+    // No stage at the given index
+    Index,
+    // Frequency outside of (0, ODR / 2) or Q not positive
+    InvalidDesign,
+}
+
+// Per axis filter state
+type AxisState = [[f32; 2]; 3];
+
+#[derive(Clone, Copy, Debug)]
+struct Transition {
+    biquad: Biquad,
+    state: AxisState,
+    // Samples since the transition started
+    progress: u16,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Stage {
+    design: FilterDesign,
+    biquad: Biquad,
+    state: AxisState,
+    // Input of the stage in the previous sample
+    input: Vec3<f32>,
+    transition: Option<Transition>,
+    // Newest section requested while a transition was running
+    queued: Option<Biquad>,
+}
+impl Stage {
+    fn apply(&mut self, input: Vec3<f32>, length: u16) -> Vec3<f32> {
+        self.input = input;
+        let run = |biquad: &Biquad, state: &mut AxisState| {
+            Vec3::new(
+                biquad.step(&mut state[0], input.x),
+                biquad.step(&mut state[1], input.y),
+                biquad.step(&mut state[2], input.z),
+            )
+        };
+        let output = run(&self.biquad, &mut self.state);
+        let Some(transition) = &mut self.transition else {
+            return output;
+        };
+        let incoming = run(&transition.biquad, &mut transition.state);
+        transition.progress += 1;
+        if transition.progress >= length {
+            self.biquad = transition.biquad;
+            self.state = transition.state;
+            self.transition = None;
+            if let Some(biquad) = self.queued.take() {
+                self.start(biquad);
+            }
+            return incoming;
+        }
+        let weight = f32::from(transition.progress) / f32::from(length);
+        output + (incoming - output) * weight
+    }
+
+    // Switches to `biquad`, cross fading over the transition length. During a
+    // transition the section is queued and the fade to it starts once the running
+    // one has finished, so every fade begins from a single settled section.
+    fn retune(&mut self, design: FilterDesign, biquad: Biquad) {
+        self.design = design;
+        if self.transition.is_some() {
+            self.queued = Some(biquad);
+        } else {
+            self.start(biquad);
+        }
+    }
+
+    // Starts a transition to `biquad`. The new section starts settled on the last
+    // input so it does not ring up from zero.
+    fn start(&mut self, biquad: Biquad) {
+        let input = self.input;
+        self.transition = Some(Transition {
+            biquad,
+            state: [
+                biquad.settled(input.x),
+                biquad.settled(input.y),
+                biquad.settled(input.z),
+            ],
+            progress: 0,
+        });
+    }
+}
+
+// Cascade of up to `N` biquad sections applied to each axis of a `Vec3<f32>`.
+//
+// Sections are designed for the output data rate, see
+// `SampleRateDivider::sample_rate_hz`. Retuning a section or changing the rate
+// while samples are flowing cross fades from the old to the new section over
+// `transition` samples instead of switching coefficients under a running
+// state. A change during a transition waits for it to finish, only the newest
+// waiting change is kept.
+#[derive(Clone, Debug)]
+pub struct FilterBank<const N: usize> {
+    sample_rate_hz: f32,
+    // Length of a cross fade in samples
+    transition: u16,
+    stages: [Option<Stage>; N],
+    len: usize,
+}
+impl<const N: usize> FilterBank<N> {
+    #[must_use]
+    pub const fn new(sample_rate_hz: f32) -> Self {
+        Self {
+            sample_rate_hz,
+            transition: 32,
+            stages: [None; N],
+            len: 0,
+        }
+    }
+
+    /// Sets the cross fade length in samples, 0 switches at the next sample
+    #[must_use]
+    pub fn with_transition(mut self, samples: u16) -> Self {
+        self.transition = samples;
+        self
+    }
+
+    #[must_use]
+    pub const fn sample_rate_hz(&self) -> f32 {
+        self.sample_rate_hz
+    }
+
+    /// Number of sections in the cascade
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Design of the section at `index`
+    #[must_use]
+    pub fn design(&self, index: usize) -> Option<FilterDesign> {
+        self.stages[..self.len]
+            .get(index)?
+            .as_ref()
+            .map(|stage| stage.design)
+    }
+
+    /// Appends a section to the end of the cascade. It starts settled on the
+    /// input of the previous sample.
+    ///
+    /// # Errors
+    /// Will error if all sections are in use or the design is invalid at the
+    /// current sample rate
+    pub fn push(&mut self, design: FilterDesign) -> Result<(), FilterError> {
+        if self.len == N {
+            return Err(FilterError::Full);
+        }
+        let biquad =
+            Biquad::design(&design, self.sample_rate_hz).ok_or(FilterError::InvalidDesign)?;
+        let input = match self.len.checked_sub(1).and_then(|last| self.stages[last]) {
+            // Output of the last section settled on its input
+            Some(last) => last.input * last.biquad.dc_gain(),
+            None => Vec3::default(),
+        };
+        self.stages[self.len] = Some(Stage {
+            design,
+            biquad,
+            state: [
+                biquad.settled(input.x),
+                biquad.settled(input.y),
+                biquad.settled(input.z),
+            ],
+            input,
+            transition: None,
+            queued: None,
+        });
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Replaces the design of the section at `index`
+    ///
+    /// # Errors
+    /// Will error if there is no such section or the design is invalid at the
+    /// current sample rate. The section is left unchanged in that case.
+    pub fn set(&mut self, index: usize, design: FilterDesign) -> Result<(), FilterError> {
+        let biquad =
+            Biquad::design(&design, self.sample_rate_hz).ok_or(FilterError::InvalidDesign)?;
+        let stage = self.stages[..self.len]
+            .get_mut(index)
+            .and_then(Option::as_mut)
+            .ok_or(FilterError::Index)?;
+        stage.retune(design, biquad);
+        Ok(())
+    }
+
+    /// Redesigns every section for a new output data rate
+    ///
+    /// # Errors
+    /// Will error if any design is invalid at the new rate. Nothing is changed in that case.
+    pub fn set_sample_rate(&mut self, sample_rate_hz: f32) -> Result<(), FilterError> {
+        let mut biquads = [Biquad::IDENTITY; N];
+        for (biquad, stage) in biquads
+            .iter_mut()
+            .zip(self.stages[..self.len].iter().flatten())
+        {
+            *biquad =
+                Biquad::design(&stage.design, sample_rate_hz).ok_or(FilterError::InvalidDesign)?;
+        }
+        self.sample_rate_hz = sample_rate_hz;
+        for (biquad, stage) in biquads
+            .into_iter()
+            .zip(self.stages[..self.len].iter_mut().flatten())
+        {
+            stage.retune(stage.design, biquad);
+        }
+        Ok(())
+    }
+
+    /// Removes all sections
+    pub fn clear(&mut self) {
+        self.stages = [None; N];
+        self.len = 0;
+    }
+
+    /// Clears the state of every section, as if the input had been zero
+    pub fn reset(&mut self) {
+        for stage in self.stages[..self.len].iter_mut().flatten() {
+            stage.state = AxisState::default();
+            stage.input = Vec3::default();
+            if let Some(transition) = &mut stage.transition {
+                transition.state = AxisState::default();
+            }
+        }
+    }
+
+    /// Filters one sample through the cascade
+    pub fn apply(&mut self, sample: Vec3<f32>) -> Vec3<f32> {
+        let transition = self.transition;
+        self.stages[..self.len]
+            .iter_mut()
+            .flatten()
+            .fold(sample, |input, stage| stage.apply(input, transition))
+    }
+}
+
+// Separate filter banks for the accelerometer and the gyroscope
+#[derive(Clone, Debug)]
+pub struct ImuFilter<const N: usize> {
+    pub accel: FilterBank<N>,
+    pub gyro: FilterBank<N>,
+}
+impl<const N: usize> ImuFilter<N> {
+    #[must_use]
+    pub const fn new(sample_rate_hz: f32) -> Self {
+        Self {
+            accel: FilterBank::new(sample_rate_hz),
+            gyro: FilterBank::new(sample_rate_hz),
+        }
+    }
+
+    /// Redesigns both banks for a new output data rate
+    ///
+    /// # Errors
+    /// Will error if any design is invalid at the new rate. The gyroscope bank is
+    /// checked first, neither bank is changed if either fails.
+    pub fn set_sample_rate(&mut self, sample_rate_hz: f32) -> Result<(), FilterError> {
+        let mut gyro = self.gyro.clone();
+        gyro.set_sample_rate(sample_rate_hz)?;
+        self.accel.set_sample_rate(sample_rate_hz)?;
+        self.gyro = gyro;
+        Ok(())
+    }
+
+    pub fn apply(&mut self, sample: &ImuSample) -> ImuSample {
+        ImuSample {
+            accel: self.accel.apply(sample.accel),
+            gyro: self.gyro.apply(sample.gyro),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::*;
+
+    // Feeds a constant 1 on every axis and returns the last output together with
+    // the largest change between two consecutive outputs
+    fn run<const N: usize>(bank: &mut FilterBank<N>, samples: usize, previous: f32) -> (f32, f32) {
+        let mut last = previous;
+        let mut largest = 0.0_f32;
+        for _ in 0..samples {
+            let output = bank.apply(Vec3::new(1.0, 1.0, 1.0));
+            assert_eq!(output.x, output.z);
+            largest = largest.max((output.x - last).abs());
+            last = output.x;
+        }
+        (last, largest)
+    }
+
+    #[test]
+    fn cross_fades_between_designs() {
+        let mut bank = FilterBank::<1>::new(1000.0).with_transition(32);
+        bank.push(FilterDesign::low_pass(10.0, FilterDesign::BUTTERWORTH_Q))
+            .unwrap();
+        let (settled, _) = run(&mut bank, 2000, 0.0);
+        assert!((settled - 1.0).abs() < 1e-4);
+
+        let high_pass = FilterDesign::high_pass(10.0, FilterDesign::BUTTERWORTH_Q);
+        bank.set(0, high_pass).unwrap();
+        let (faded, largest) = run(&mut bank, 32, settled);
+        assert!(faded.abs() < 1e-4);
+        assert!(largest < 1.1 / 32.0, "{largest}");
+        assert_eq!(bank.design(0), Some(high_pass));
+    }
+
+    #[test]
+    fn retune_during_fade_starts_a_new_fade() {
+        let mut bank = FilterBank::<1>::new(1000.0).with_transition(32);
+        bank.push(FilterDesign::low_pass(10.0, FilterDesign::BUTTERWORTH_Q))
+            .unwrap();
+        let (settled, _) = run(&mut bank, 2000, 0.0);
+
+        bank.set(
+            0,
+            FilterDesign::high_pass(10.0, FilterDesign::BUTTERWORTH_Q),
+        )
+        .unwrap();
+        let (midway, first) = run(&mut bank, 16, settled);
+        let low_pass = FilterDesign::low_pass(50.0, FilterDesign::BUTTERWORTH_Q);
+        bank.set(0, low_pass).unwrap();
+        assert_eq!(bank.design(0), Some(low_pass));
+        let (last, second) = run(&mut bank, 200, midway);
+        assert!(first.max(second) < 1.1 / 32.0, "{first} {second}");
+        assert!((last - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rejects_invalid_changes_without_side_effects() {
+        let mut bank = FilterBank::<2>::new(1000.0);
+        let low_pass = FilterDesign::low_pass(100.0, FilterDesign::BUTTERWORTH_Q);
+        bank.push(low_pass).unwrap();
+        assert_eq!(
+            bank.push(FilterDesign::low_pass(600.0, 1.0)),
+            Err(FilterError::InvalidDesign)
+        );
+        bank.push(FilterDesign::notch(400.0, 2.0)).unwrap();
+        assert_eq!(bank.push(low_pass), Err(FilterError::Full));
+        assert_eq!(bank.set(2, low_pass), Err(FilterError::Index));
+        assert_eq!(
+            bank.set(0, FilterDesign::low_pass(100.0, 0.0)),
+            Err(FilterError::InvalidDesign)
+        );
+        assert_eq!(bank.set_sample_rate(500.0), Err(FilterError::InvalidDesign));
+        assert_eq!(bank.sample_rate_hz(), 1000.0);
+        assert_eq!(bank.design(0), Some(low_pass));
+        bank.set_sample_rate(2000.0).unwrap();
+        assert_eq!(bank.sample_rate_hz(), 2000.0);
+    }
+
+    #[test]
+    fn imu_filter_changes_both_banks_or_neither() {
+        let mut filter = ImuFilter::<1>::new(1000.0);
+        filter
+            .accel
+            .push(FilterDesign::low_pass(400.0, FilterDesign::BUTTERWORTH_Q))
+            .unwrap();
+        filter
+            .gyro
+            .push(FilterDesign::low_pass(100.0, FilterDesign::BUTTERWORTH_Q))
+            .unwrap();
+        assert!(filter.set_sample_rate(500.0).is_err());
+        assert_eq!(filter.accel.sample_rate_hz(), 1000.0);
+        assert_eq!(filter.gyro.sample_rate_hz(), 1000.0);
+
+        filter
+            .gyro
+            .set(
+                0,
+                FilterDesign::low_pass(300.0, FilterDesign::BUTTERWORTH_Q),
+            )
+            .unwrap();
+        filter
+            .accel
+            .set(
+                0,
+                FilterDesign::low_pass(100.0, FilterDesign::BUTTERWORTH_Q),
+            )
+            .unwrap();
+        assert!(filter.set_sample_rate(500.0).is_err());
+        assert_eq!(filter.accel.sample_rate_hz(), 1000.0);
+        filter.set_sample_rate(800.0).unwrap();
+        assert_eq!(filter.accel.sample_rate_hz(), 800.0);
+        assert_eq!(filter.gyro.sample_rate_hz(), 800.0);
+    }
+}
@@ -0,0 +1,230 @@
+use embedded_hal::i2c::I2c;
+
+use crate::fifo::FifoFrame;
+use crate::structs::{
+    AccelMeasurements, Config, GyroscopeMeasurements, InterruptPinConfig, ReadRegister,
+    TemperatureMeasurements, WriteRegister,
+};
+
+// Output register whose LSB is replaced by the latched FSYNC pin state.
+// The discriminant is the matching `Config::ext_sync_set` value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsyncSource {
+    Temperature = 1,
+    GyroX = 2,
+    GyroY = 3,
+    GyroZ = 4,
+    AccelX = 5,
+    AccelY = 6,
+    AccelZ = 7,
+}
+impl FsyncSource {
+    #[must_use]
+    pub const fn ext_sync_set(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns None for 0, which disables FSYNC sampling
+    #[must_use]
+    pub const fn from_ext_sync_set(value: u8) -> Option<Self> {
+        match value & 0b111 {
+            1 => Some(Self::Temperature),
+            2 => Some(Self::GyroX),
+            3 => Some(Self::GyroY),
+            4 => Some(Self::GyroZ),
+            5 => Some(Self::AccelX),
+            6 => Some(Self::AccelY),
+            7 => Some(Self::AccelZ),
+            _ => None,
+        }
+    }
+
+    /// Clears the tag bit from the tagged measurement of the frame and returns
+    /// whether it was set. Frames that do not carry the tagged measurement are
+    /// never tagged.
+    pub fn strip_frame(self, frame: &mut FifoFrame) -> bool {
+        let tagged = match self {
+            Self::Temperature => frame
+                .temperature
+                .as_mut()
+                .is_some_and(|temperature| self.strip_temperature(temperature)),
+            Self::GyroX | Self::GyroY | Self::GyroZ => frame
+                .gyro
+                .as_mut()
+                .is_some_and(|gyro| self.strip_gyro(gyro)),
+            Self::AccelX | Self::AccelY | Self::AccelZ => frame
+                .accel
+                .as_mut()
+                .is_some_and(|accel| self.strip_accel(accel)),
+        };
+        frame.fsync = tagged;
+        tagged
+    }
+
+    /// Clears the tag bit if it is carried by the accelerometer and returns whether it was set
+    pub fn strip_accel(self, accel: &mut AccelMeasurements) -> bool {
+        match self {
+            Self::AccelX => strip(&mut accel.x),
+            Self::AccelY => strip(&mut accel.y),
+            Self::AccelZ => strip(&mut accel.z),
+            _ => false,
+        }
+    }
+
+    /// Clears the tag bit if it is carried by the gyroscope and returns whether it was set
+    pub fn strip_gyro(self, gyro: &mut GyroscopeMeasurements) -> bool {
+        match self {
+            Self::GyroX => strip(&mut gyro.x),
+            Self::GyroY => strip(&mut gyro.y),
+            Self::GyroZ => strip(&mut gyro.z),
+            _ => false,
+        }
+    }
+
+    /// Clears the tag bit if it is carried by the temperature and returns whether it was set
+    pub fn strip_temperature(self, temperature: &mut TemperatureMeasurements) -> bool {
+        match self {
+            Self::Temperature => strip(&mut temperature.temp_out),
+            _ => false,
+        }
+    }
+}
+
+fn strip(word: &mut i16) -> bool {
+    let tagged = *word & 1 != 0;
+    *word &= !1;
+    tagged
+}
+
+/// Latches the FSYNC pin into the LSB of the `source` register, or disables FSYNC
+/// sampling when `source` is None. Only `Config::ext_sync_set` is modified.
+///
+/// # Errors
+/// Will error if unable to communicate with the device
+pub fn set_source<I: I2c>(i2c: &mut I, source: Option<FsyncSource>) -> Result<(), I::Error> {
+    let mut config = Config::new(i2c)?;
+    config.ext_sync_set = source.map_or(0, FsyncSource::ext_sync_set);
+    config.write(i2c)
+}
+
+/// Reads back which register currently carries the FSYNC tag
+///
+/// # Errors
+/// Will error if unable to communicate with the device
+pub fn source<I: I2c>(i2c: &mut I) -> Result<Option<FsyncSource>, I::Error> {
+    Ok(FsyncSource::from_ext_sync_set(
+        Config::new(i2c)?.ext_sync_set,
+    ))
+}
+
+/// Enables or disables the FSYNC pin as an interrupt source. The interrupt is
+/// reported through `FsyncInterrupt`.
+///
+/// # Errors
+/// Will error if unable to communicate with the device
+pub fn set_interrupt<I: I2c>(i2c: &mut I, enabled: bool, active_low: bool) -> Result<(), I::Error> {
+    let mut pin_config = InterruptPinConfig::new(i2c)?;
+    pin_config.fsync_int_mode_en = enabled;
+    pin_config.fsync_int_level = active_low;
+    pin_config.write(i2c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeDevice;
+
+    const SOURCES: [FsyncSource; 7] = [
+        FsyncSource::Temperature,
+        FsyncSource::GyroX,
+        FsyncSource::GyroY,
+        FsyncSource::GyroZ,
+        FsyncSource::AccelX,
+        FsyncSource::AccelY,
+        FsyncSource::AccelZ,
+    ];
+
+    // Frame built from words in register order: temperature, gyro X/Y/Z, accel X/Y/Z
+    fn frame(words: [i16; 7]) -> FifoFrame {
+        FifoFrame {
+            accel: Some(AccelMeasurements {
+                x: words[4],
+                y: words[5],
+                z: words[6],
+            }),
+            temperature: Some(TemperatureMeasurements { temp_out: words[0] }),
+            gyro: Some(GyroscopeMeasurements {
+                x: words[1],
+                y: words[2],
+                z: words[3],
+            }),
+            fsync: false,
+        }
+    }
+
+    // All words odd, so every source sees its tag set
+    fn tagged_frame() -> FifoFrame {
+        frame([9, -11, -13, -15, 3, 5, 7])
+    }
+
+    // Words of the frame in register order
+    fn words(frame: &FifoFrame) -> [i16; 7] {
+        let accel = frame.accel.unwrap();
+        let gyro = frame.gyro.unwrap();
+        [
+            frame.temperature.unwrap().temp_out,
+            gyro.x,
+            gyro.y,
+            gyro.z,
+            accel.x,
+            accel.y,
+            accel.z,
+        ]
+    }
+
+    #[test]
+    fn strips_only_the_tagged_measurement() {
+        let original = words(&tagged_frame());
+        for (index, source) in SOURCES.into_iter().enumerate() {
+            let mut frame = tagged_frame();
+            assert!(source.strip_frame(&mut frame));
+            assert!(frame.fsync);
+            for (i, (&word, &before)) in words(&frame).iter().zip(&original).enumerate() {
+                let expected = if i == index { before & !1 } else { before };
+                assert_eq!(word, expected, "{source:?} word {i}");
+            }
+        }
+    }
+
+    #[test]
+    fn untagged_or_missing_measurements_report_false() {
+        for source in SOURCES {
+            let mut frame = frame(words(&tagged_frame()).map(|word| word & !1));
+            frame.fsync = true;
+            assert!(!source.strip_frame(&mut frame));
+            assert!(!frame.fsync);
+
+            let mut empty = FifoFrame::default();
+            assert!(!source.strip_frame(&mut empty));
+        }
+        assert_eq!(FsyncSource::from_ext_sync_set(0), None);
+    }
+
+    #[test]
+    fn set_source_only_changes_ext_sync_set() {
+        let mut device = FakeDevice::new();
+        // fifo_mode and dlpf_cfg = 6
+        device.registers[0x1A] = (1 << 6) | 6;
+        for source in SOURCES {
+            set_source(&mut device, Some(source)).unwrap();
+            assert_eq!(
+                device.registers[0x1A],
+                (1 << 6) | (source.ext_sync_set() << 3) | 6
+            );
+            assert_eq!(self::source(&mut device).unwrap(), Some(source));
+        }
+        set_source(&mut device, None).unwrap();
+        assert_eq!(device.registers[0x1A], (1 << 6) | 6);
+        assert_eq!(self::source(&mut device).unwrap(), None);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::orientation::Quaternion;
+use crate::structs::Vec3;
+
+// Standard acceleration of gravity in m/s², to convert the results from g
+pub const STANDARD_GRAVITY: f32 = 9.806_65;
+
+// Up in the earth frame, the acceleration measured at rest
+const UP: Vec3<f32> = Vec3::new(0.0, 0.0, 1.0);
+
+// Accelerometer sample split into gravity and linear acceleration, in g
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinearAcceleration {
+    // Gravity reaction in the sensor frame, what the accelerometer reads at rest
+    pub gravity: Vec3<f32>,
+    // Linear acceleration in the sensor frame
+    pub body: Vec3<f32>,
+    // Linear acceleration in the earth frame, Z up
+    pub world: Vec3<f32>,
+}
+
+/// Removes gravity from an accelerometer sample in g using the orientation from
+/// one of the filters in `orientation`
+#[must_use]
+pub fn separate(orientation: &Quaternion, accel: Vec3<f32>) -> LinearAcceleration {
+    let gravity = orientation.rotate_inverse(UP);
+    let body = accel - gravity;
+    LinearAcceleration {
+        gravity,
+        body,
+        world: orientation.rotate(body),
+    }
+}
+
+// Gravity estimate for when no orientation filter is running.
+//
+// Gravity is tracked with a first order low pass on the accelerometer, so
+// linear acceleration lasting longer than the time constant leaks into the
+// estimate. The world frame is levelled with the estimate and has an arbitrary
+// heading.
+pub struct GravityFilter {
+    sample_rate_hz: f32,
+    // Weight of a new sample
+    weight: f32,
+    gravity: Option<Vec3<f32>>,
+}
+impl GravityFilter {
+    /// Filter with a time constant of 1 s
+    #[must_use]
+    pub fn new(sample_rate_hz: f32) -> Self {
+        Self {
+            sample_rate_hz,
+            weight: 0.0,
+            gravity: None,
+        }
+        .with_time_constant(1.0)
+    }
+
+    #[must_use]
+    pub fn with_time_constant(mut self, seconds: f32) -> Self {
+        self.weight = 1.0 / (1.0 + seconds * self.sample_rate_hz);
+        self
+    }
+
+    /// Current estimate in g, None before the first sample
+    #[must_use]
+    pub const fn gravity(&self) -> Option<Vec3<f32>> {
+        self.gravity
+    }
+
+    pub fn reset(&mut self) {
+        self.gravity = None;
+    }
+
+    /// Processes one accelerometer sample in g. The first sample is taken as gravity.
+    pub fn update(&mut self, accel: Vec3<f32>) -> LinearAcceleration {
+        let gravity = match self.gravity {
+            Some(gravity) => gravity + (accel - gravity) * self.weight,
+            None => accel,
+        };
+        self.gravity = Some(gravity);
+        let body = accel - gravity;
+        LinearAcceleration {
+            gravity,
+            body,
+            world: Quaternion::from_vectors(gravity, UP).rotate(body),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_constant_sets_the_response() {
+        let step = |filter: &mut GravityFilter| {
+            filter.update(Vec3::new(0.0, 0.0, 1.0));
+            for _ in 0..99 {
+                filter.update(Vec3::new(0.0, 1.0, 1.0));
+            }
+            filter.gravity().unwrap().y
+        };
+        // After one time constant about 63 % of a step has reached the estimate
+        let slow = step(&mut GravityFilter::new(100.0));
+        assert!((slow - 0.63).abs() < 0.01, "{slow}");
+        let fast = step(&mut GravityFilter::new(100.0).with_time_constant(0.25));
+        assert!((fast - 0.98).abs() < 0.01, "{fast}");
+    }
+}
@@ -0,0 +1,202 @@
+use core::f32::consts::PI;
+
+use crate::sample::SampleScale;
+use crate::structs::{AccelMeasurements, GyroscopeMeasurements, Vec3};
+
+// Largest pitch in radians used for the roll rate. Roll is undefined when the X
+// axis points straight up or down and tan(pitch) grows without bound there.
+const MAX_RATE_PITCH: f32 = PI / 2.0 - 0.01;
+
+// Inclination in radians. Roll and pitch follow the yaw-pitch-roll convention of
+// `RotationOrder::Zyx`: roll turns about the sensor X axis, pitch about Y.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tilt {
+    // Within [-π, π]
+    pub roll: f32,
+    // Within [-π/2, π/2]
+    pub pitch: f32,
+    // Angle between the sensor Z axis and the vertical, within [0, π]
+    pub tilt: f32,
+}
+impl Tilt {
+    #[must_use]
+    pub fn new(roll: f32, pitch: f32) -> Self {
+        let tilt = libm::acosf((libm::cosf(roll) * libm::cosf(pitch)).clamp(-1.0, 1.0));
+        Self { roll, pitch, tilt }
+    }
+
+    /// Inclination from the direction of gravity in any unit, None for a zero vector
+    #[must_use]
+    pub fn from_gravity(gravity: Vec3<f32>) -> Option<Self> {
+        let gravity = gravity.normalized()?;
+        let roll = libm::atan2f(gravity.y, gravity.z);
+        let pitch = libm::atan2f(-gravity.x, libm::hypotf(gravity.y, gravity.z));
+        Some(Self {
+            roll,
+            pitch,
+            tilt: libm::acosf(gravity.z.clamp(-1.0, 1.0)),
+        })
+    }
+}
+
+// Pitch and roll from the accelerometer, optionally blended with the gyroscope.
+//
+// Without gyroscope samples every accepted accelerometer sample is converted
+// directly. With them, the angles are integrated from the angular rate and pulled
+// towards the accelerometer angles with `time_constant`: shorter reacts faster,
+// longer rejects more vibration. Accelerometer samples whose magnitude differs
+// from 1 g by more than `gravity_tolerance` are ignored, the angles then follow
+// the gyroscope alone or hold their last value.
+pub struct Inclinometer {
+    scale: SampleScale,
+    // Seconds
+    time_constant: f32,
+    // g
+    gravity_tolerance: f32,
+    // Roll and pitch, None before the first accepted sample
+    angles: Option<(f32, f32)>,
+}
+impl Inclinometer {
+    #[must_use]
+    pub const fn new(scale: SampleScale) -> Self {
+        Self {
+            scale,
+            time_constant: 1.0,
+            gravity_tolerance: 0.1,
+            angles: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_time_constant(mut self, seconds: f32) -> Self {
+        self.time_constant = seconds;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_gravity_tolerance(mut self, tolerance: f32) -> Self {
+        self.gravity_tolerance = tolerance;
+        self
+    }
+
+    pub fn set_time_constant(&mut self, seconds: f32) {
+        self.time_constant = seconds;
+    }
+
+    /// Latest inclination, None before the first accepted sample
+    #[must_use]
+    pub fn tilt(&self) -> Option<Tilt> {
+        self.angles.map(|(roll, pitch)| Tilt::new(roll, pitch))
+    }
+
+    /// Restarts from the next accepted accelerometer sample
+    pub fn reset(&mut self) {
+        self.angles = None;
+    }
+
+    /// Processes one sample taken `dt` seconds after the previous one
+    pub fn update(
+        &mut self,
+        accel: &AccelMeasurements,
+        gyro: Option<&GyroscopeMeasurements>,
+        dt: f32,
+    ) -> Option<Tilt> {
+        let accel = self.scale.accel(accel);
+        let measured = if (accel.norm() - 1.0).abs() <= self.gravity_tolerance {
+            Tilt::from_gravity(accel)
+        } else {
+            None
+        };
+
+        self.angles = match (self.angles, gyro, measured) {
+            (Some((roll, pitch)), Some(gyro), measured) => {
+                let rate = self.scale.gyro(gyro);
+                // Euler angle rates of the yaw-pitch-roll sequence
+                let (sin_roll, cos_roll) = libm::sincosf(roll);
+                let roll_rate = rate.x
+                    + (rate.y * sin_roll + rate.z * cos_roll)
+                        * libm::tanf(pitch.clamp(-MAX_RATE_PITCH, MAX_RATE_PITCH));
+                let pitch_rate = rate.y * cos_roll - rate.z * sin_roll;
+                let roll = wrap(roll + roll_rate * dt);
+                let pitch = (pitch + pitch_rate * dt).clamp(-PI / 2.0, PI / 2.0);
+                match measured {
+                    Some(measured) => {
+                        let weight = dt / (self.time_constant + dt);
+                        Some((
+                            wrap(roll + wrap(measured.roll - roll) * weight),
+                            pitch + (measured.pitch - pitch) * weight,
+                        ))
+                    }
+                    None => Some((roll, pitch)),
+                }
+            }
+            (_, _, Some(measured)) => Some((measured.roll, measured.pitch)),
+            (angles, _, None) => angles,
+        };
+        self.tilt()
+    }
+}
+
+// Wraps an angle into [-π, π]
+fn wrap(angle: f32) -> f32 {
+    libm::remainderf(angle, 2.0 * PI)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeDevice;
+
+    // ±2 g and ±250 º/s, the reset values of the full scale registers
+    fn scale() -> SampleScale {
+        SampleScale::read(&mut FakeDevice::new()).unwrap()
+    }
+
+    #[test]
+    fn roll_stays_bounded_near_vertical() {
+        let mut inclinometer = Inclinometer::new(scale());
+        // X axis pointing down, pitch at +π/2
+        let vertical = AccelMeasurements {
+            x: -16_384,
+            y: 0,
+            z: 0,
+        };
+        let tilt = inclinometer.update(&vertical, None, 0.01).unwrap();
+        assert!((tilt.pitch - PI / 2.0).abs() < 1e-6);
+        let gyro = GyroscopeMeasurements {
+            x: 0,
+            y: 0,
+            z: 1_310,
+        };
+        let roll = inclinometer
+            .update(&vertical, Some(&gyro), 0.01)
+            .unwrap()
+            .roll;
+        // 10 º/s about Z for 10 ms, amplified by at most tan(MAX_RATE_PITCH) ≈ 100
+        assert!(roll.abs() < 0.2, "{roll}");
+    }
+
+    #[test]
+    fn wraps_large_steps_into_range() {
+        assert!((wrap(5.0 * PI + 0.25) - (-PI + 0.25)).abs() < 1e-4);
+        assert!((wrap(-7.5 * PI) - PI / 2.0).abs() < 1e-4);
+
+        let mut inclinometer = Inclinometer::new(scale());
+        let level = AccelMeasurements {
+            x: 0,
+            y: 0,
+            z: 16_384,
+        };
+        inclinometer.update(&level, None, 0.01);
+        let spin = GyroscopeMeasurements {
+            x: i16::MAX,
+            y: 0,
+            z: 0,
+        };
+        // Gyro only: 250 º/s over 3 s
+        let tilt = inclinometer
+            .update(&AccelMeasurements::default(), Some(&spin), 3.0)
+            .unwrap();
+        assert!((-PI..=PI).contains(&tilt.roll), "{tilt:?}");
+    }
+}
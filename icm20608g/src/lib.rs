@@ -1,3 +1,18 @@
 #![cfg_attr(not(feature = "visualize"), no_std)]
 #[allow(dead_code)]
 pub mod structs;
+
+pub mod analysis;
+pub mod calibration;
+pub mod fifo;
+pub mod filter;
+pub mod fsync;
+pub mod gravity;
+pub mod inclinometer;
+pub mod motion;
+pub mod navigation;
+pub mod orientation;
+pub mod sample;
+
+#[cfg(test)]
+mod test_support;
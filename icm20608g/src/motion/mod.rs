@@ -0,0 +1,34 @@
+use crate::structs::Vec3;
+
+mod pedometer;
+mod posture;
+mod shock;
+mod stationary;
+mod tap;
+pub use pedometer::{Pedometer, PedometerError};
+pub use posture::{DeviceOrientation, OrientationClassifier, ScreenOrientation};
+pub use shock::{
+    arm_wake_on_motion, disarm_wake_on_motion, AccelEvent, FreeFallDetector, ImpactDetector,
+};
+pub use stationary::{MotionState, StationaryDetector};
+pub use tap::{TapDetector, TapEvent, TapKind};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+impl Axis {
+    // Axis with the largest magnitude and its signed value
+    pub(crate) fn largest(vector: Vec3<f32>) -> (Self, f32) {
+        [
+            (Self::X, vector.x),
+            (Self::Y, vector.y),
+            (Self::Z, vector.z),
+        ]
+        .into_iter()
+        .reduce(|a, b| if b.1.abs() > a.1.abs() { b } else { a })
+        .unwrap_or((Self::X, vector.x))
+    }
+}
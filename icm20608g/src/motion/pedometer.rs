@@ -0,0 +1,296 @@
+use core::f32::consts::PI;
+
+use crate::structs::Vec3;
+
+// Corner frequency in Hz of the smoothing applied to the magnitude
+const SMOOTHING_HZ: f32 = 3.0;
+// Time constant in seconds of the gravity estimate removed from the magnitude
+const GRAVITY_TIME_CONSTANT: f32 = 1.0;
+// Weight of a new step in the amplitude and interval averages
+const STEP_WEIGHT: f32 = 0.3;
+// Fraction of the average step amplitude a peak has to reach
+const THRESHOLD_RATIO: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PedometerError {
+    // Output data rate below `Pedometer::MIN_SAMPLE_RATE_HZ`
+    SampleRate,
+}
+
+// Step counter on the accelerometer magnitude.
+//
+// The magnitude is smoothed and gravity is removed, so the orientation of the
+// device does not matter. A step is a peak above an adaptive threshold, half of
+// the recent step amplitude but at least `min_amplitude`, followed by a zero
+// crossing. Peaks are only steps if they fall into the cadence window: at least
+// `min_interval` after the previous step. Steps are counted once `confirm_steps`
+// of them arrived with no more than `max_interval` between them, which filters
+// out isolated bumps. The walk ends when no step arrives within `max_interval`.
+//
+// Works at the low-power accelerometer rates from `LowPowerModeConf::accel_rate_hz`
+// down to 7.81 Hz (`lposc_clksel` 5) and at any faster rate. At 3.91 Hz and below
+// a brisk walk of more than two steps per second is above the Nyquist frequency,
+// those rates are rejected.
+pub struct Pedometer {
+    sample_rate_hz: f32,
+    // g
+    min_amplitude: f32,
+    // Samples
+    min_interval: u32,
+    max_interval: u32,
+    confirm_steps: u32,
+    smoothing_weight: f32,
+    gravity_weight: f32,
+
+    sample: u64,
+    smoothed: Option<f32>,
+    gravity: f32,
+    // Highest value since the signal rose above the threshold
+    peak: Option<f32>,
+    amplitude: f32,
+    last_step: Option<u64>,
+    // Average step interval in samples
+    interval: f32,
+    // Consecutive steps within the cadence window
+    run: u32,
+    walking: bool,
+    steps: u32,
+}
+impl Pedometer {
+    // Slowest supported output data rate in Hz, with some margin below 7.81 Hz
+    pub const MIN_SAMPLE_RATE_HZ: f32 = 7.5;
+
+    /// Creates a step counter for samples at `sample_rate_hz`
+    ///
+    /// # Errors
+    /// Will error if the rate is below `MIN_SAMPLE_RATE_HZ`
+    pub fn new(sample_rate_hz: f32) -> Result<Self, PedometerError> {
+        if sample_rate_hz >= Self::MIN_SAMPLE_RATE_HZ {
+            Ok(Self::initial(sample_rate_hz))
+        } else {
+            Err(PedometerError::SampleRate)
+        }
+    }
+
+    fn initial(sample_rate_hz: f32) -> Self {
+        let mut pedometer = Self {
+            sample_rate_hz,
+            min_amplitude: 0.05,
+            min_interval: 0,
+            max_interval: 0,
+            confirm_steps: 4,
+            smoothing_weight: (1.0 - libm::expf(-2.0 * PI * SMOOTHING_HZ / sample_rate_hz)),
+            gravity_weight: (1.0 / (GRAVITY_TIME_CONSTANT * sample_rate_hz)).min(1.0),
+            sample: 0,
+            smoothed: None,
+            gravity: 1.0,
+            peak: None,
+            amplitude: 0.0,
+            last_step: None,
+            interval: 0.0,
+            run: 0,
+            walking: false,
+            steps: 0,
+        };
+        pedometer.min_interval = pedometer.samples(0.25);
+        pedometer.max_interval = pedometer.samples(2.0);
+        pedometer
+    }
+
+    /// Smallest peak in g above the gravity estimate that can be a step
+    #[must_use]
+    pub const fn with_min_amplitude(mut self, amplitude: f32) -> Self {
+        self.min_amplitude = amplitude;
+        self
+    }
+
+    /// Shortest and longest time between steps in seconds, by default 0.25 s to 2 s
+    #[must_use]
+    pub fn with_cadence_window(mut self, min_interval: f32, max_interval: f32) -> Self {
+        self.min_interval = self.samples(min_interval);
+        self.max_interval = self.samples(max_interval);
+        self
+    }
+
+    /// Number of steps in a row needed before a walk is counted
+    #[must_use]
+    pub fn with_confirm_steps(mut self, steps: u32) -> Self {
+        self.confirm_steps = steps.max(1);
+        self
+    }
+
+    /// Steps counted since the pedometer was created or reset
+    #[must_use]
+    pub const fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    #[must_use]
+    pub const fn is_walking(&self) -> bool {
+        self.walking
+    }
+
+    /// Steps per minute while walking, otherwise 0
+    #[must_use]
+    pub fn cadence(&self) -> f32 {
+        if self.walking && self.interval > 0.0 {
+            60.0 * self.sample_rate_hz / self.interval
+        } else {
+            0.0
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self {
+            min_amplitude: self.min_amplitude,
+            min_interval: self.min_interval,
+            max_interval: self.max_interval,
+            confirm_steps: self.confirm_steps,
+            ..Self::initial(self.sample_rate_hz)
+        };
+    }
+
+    /// Processes one accelerometer sample in g, returns the number of steps that
+    /// were counted with it. Confirming a walk counts all of its steps at once.
+    pub fn update(&mut self, accel: Vec3<f32>) -> u32 {
+        let sample = self.sample;
+        self.sample += 1;
+
+        let magnitude = accel.norm();
+        let smoothed = if let Some(smoothed) = self.smoothed {
+            smoothed + (magnitude - smoothed) * self.smoothing_weight
+        } else {
+            self.gravity = magnitude;
+            magnitude
+        };
+        self.smoothed = Some(smoothed);
+        self.gravity += (smoothed - self.gravity) * self.gravity_weight;
+        let signal = smoothed - self.gravity;
+
+        if let Some(last) = self.last_step {
+            if sample - last > u64::from(self.max_interval) {
+                self.walking = false;
+                self.run = 0;
+                self.amplitude = 0.0;
+            }
+        }
+
+        let threshold = (self.amplitude * THRESHOLD_RATIO).max(self.min_amplitude);
+        match self.peak {
+            Some(peak) if signal > 0.0 => self.peak = Some(peak.max(signal)),
+            Some(peak) => {
+                self.peak = None;
+                return self.step(sample, peak);
+            }
+            None if signal > threshold => self.peak = Some(signal),
+            None => {}
+        }
+        0
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn step(&mut self, sample: u64, peak: f32) -> u32 {
+        let interval = self.last_step.map(|last| sample - last);
+        if interval.is_some_and(|interval| interval < u64::from(self.min_interval)) {
+            return 0;
+        }
+        self.last_step = Some(sample);
+        self.amplitude = if self.amplitude > 0.0 {
+            self.amplitude + (peak - self.amplitude) * STEP_WEIGHT
+        } else {
+            peak
+        };
+        match interval {
+            Some(interval) if self.run > 0 && interval <= u64::from(self.max_interval) => {
+                let interval = interval as f32;
+                self.interval = if self.run > 1 {
+                    self.interval + (interval - self.interval) * STEP_WEIGHT
+                } else {
+                    interval
+                };
+                self.run += 1;
+            }
+            _ => self.run = 1,
+        }
+
+        let counted = if self.walking {
+            1
+        } else if self.run >= self.confirm_steps {
+            self.walking = true;
+            self.run
+        } else {
+            0
+        };
+        self.steps += counted;
+        counted
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn samples(&self, seconds: f32) -> u32 {
+        libm::roundf((seconds * self.sample_rate_hz).max(0.0)) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::LowPowerModeConf;
+
+    // Stands still for 3 s, walks at `cadence` steps per second for 20 s, then
+    // stands still again. Returns the counted steps.
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn walk(sample_rate_hz: f32, cadence: f32) -> f32 {
+        let mut pedometer = Pedometer::new(sample_rate_hz).unwrap();
+        let samples = (26.0 * sample_rate_hz) as u32;
+        for n in 0..samples {
+            let t = n as f32 / sample_rate_hz;
+            let bounce = if (3.0..23.0).contains(&t) {
+                0.3 * libm::sinf(2.0 * PI * cadence * (t - 3.0))
+            } else {
+                0.0
+            };
+            pedometer.update(Vec3::new(0.1, 0.0, 1.0 + bounce));
+        }
+        assert!(!pedometer.is_walking());
+        pedometer.steps() as f32
+    }
+
+    #[test]
+    fn counts_steps_at_low_power_rates() {
+        for lposc_clksel in 5..=11 {
+            let rate = LowPowerModeConf {
+                gyro_cycle: false,
+                g_avgcfg: 0,
+                lposc_clksel,
+            }
+            .accel_rate_hz();
+            for cadence in [1.2, 2.0] {
+                let steps = walk(rate, cadence);
+                assert!(
+                    (steps - 20.0 * cadence).abs() <= 2.0,
+                    "{rate} Hz, {cadence}/s: {steps}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_rates_too_slow_for_walking() {
+        assert_eq!(Pedometer::new(3.9).err(), Some(PedometerError::SampleRate));
+        assert!(Pedometer::new(7.8125).is_ok());
+    }
+
+    #[test]
+    fn ignores_isolated_bumps() {
+        let mut pedometer = Pedometer::new(50.0).unwrap();
+        for n in 0..500 {
+            let z = if n % 150 == 100 { 1.5 } else { 1.0 };
+            pedometer.update(Vec3::new(0.0, 0.0, z));
+        }
+        assert_eq!(pedometer.steps(), 0);
+    }
+}
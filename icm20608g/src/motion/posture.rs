@@ -0,0 +1,343 @@
+use core::f32::consts::PI;
+
+use crate::sample::Face;
+use crate::structs::Vec3;
+
+// Rotation of a screen in the sensor X-Y plane, named after the axis pointing up
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScreenOrientation {
+    // +Y up
+    #[default]
+    Portrait,
+    // +X up, the device turned counterclockwise from portrait
+    LandscapeLeft,
+    // -Y up
+    PortraitInverted,
+    // -X up, the device turned clockwise from portrait
+    LandscapeRight,
+}
+impl ScreenOrientation {
+    // Angle of the up direction in the X-Y plane, from +Y towards +X
+    const fn center(self) -> f32 {
+        match self {
+            Self::Portrait => 0.0,
+            Self::LandscapeLeft => PI / 2.0,
+            Self::PortraitInverted => PI,
+            Self::LandscapeRight => -PI / 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceOrientation {
+    // Sensor axis pointing up, None until an axis was first held vertical. The
+    // last face is kept while the device is tilted between faces.
+    pub face: Option<Face>,
+    // Last screen rotation, kept while the device lies flat
+    pub screen: ScreenOrientation,
+}
+
+// A candidate that has to persist for a number of samples
+#[derive(Clone, Copy, Debug)]
+struct Debounce<T> {
+    candidate: Option<T>,
+    count: u32,
+}
+impl<T: Copy + PartialEq> Debounce<T> {
+    const fn new() -> Self {
+        Self {
+            candidate: None,
+            count: 0,
+        }
+    }
+
+    // Returns the candidate once it was seen `samples` times in a row
+    fn update(&mut self, candidate: T, samples: u32) -> Option<T> {
+        if self.candidate == Some(candidate) {
+            self.count = self.count.saturating_add(1);
+        } else {
+            self.candidate = Some(candidate);
+            self.count = 1;
+        }
+        (self.count >= samples).then_some(candidate)
+    }
+
+    fn clear(&mut self) {
+        self.candidate = None;
+        self.count = 0;
+    }
+}
+
+// Screen rotation style orientation events from the accelerometer.
+//
+// The face is the axis within `tilt_tolerance` of vertical. The screen
+// orientation follows the direction of gravity in the X-Y plane and is only
+// updated while that plane is tilted by at least `screen_min_tilt`. Leaving the
+// current state takes `hysteresis` more than entering it, and a new state has
+// to persist for the debounce time before it is reported. Samples more than
+// 0.3 g away from 1 g are ignored as the device is being moved.
+//
+// Changes are returned by `update` and passed to the callback set with
+// `with_callback`.
+pub struct OrientationClassifier<F> {
+    // Radians
+    tilt_tolerance: f32,
+    screen_min_tilt: f32,
+    hysteresis: f32,
+    // Samples
+    debounce: u32,
+    callback: F,
+
+    face: Option<Face>,
+    screen: ScreenOrientation,
+    pending_face: Debounce<Face>,
+    pending_screen: Debounce<ScreenOrientation>,
+}
+impl OrientationClassifier<fn(DeviceOrientation)> {
+    /// Classifier with a tilt tolerance of 30º, hysteresis of 10º and a debounce
+    /// time of 0.2 s
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn new(sample_rate_hz: f32) -> Self {
+        Self {
+            tilt_tolerance: 30_f32.to_radians(),
+            screen_min_tilt: 25_f32.to_radians(),
+            hysteresis: 10_f32.to_radians(),
+            debounce: (libm::roundf(sample_rate_hz * 0.2) as u32).max(1),
+            callback: |_| {},
+            face: None,
+            screen: ScreenOrientation::Portrait,
+            pending_face: Debounce::new(),
+            pending_screen: Debounce::new(),
+        }
+    }
+}
+impl<F: FnMut(DeviceOrientation)> OrientationClassifier<F> {
+    /// Calls `callback` with the new orientation on every change
+    #[must_use]
+    pub fn with_callback<G: FnMut(DeviceOrientation)>(
+        self,
+        callback: G,
+    ) -> OrientationClassifier<G> {
+        OrientationClassifier {
+            tilt_tolerance: self.tilt_tolerance,
+            screen_min_tilt: self.screen_min_tilt,
+            hysteresis: self.hysteresis,
+            debounce: self.debounce,
+            callback,
+            face: self.face,
+            screen: self.screen,
+            pending_face: self.pending_face,
+            pending_screen: self.pending_screen,
+        }
+    }
+
+    /// Largest angle in radians between an axis and vertical for its face
+    #[must_use]
+    pub const fn with_tilt_tolerance(mut self, radians: f32) -> Self {
+        self.tilt_tolerance = radians;
+        self
+    }
+
+    /// Smallest tilt in radians of the X-Y plane from horizontal for the screen
+    /// orientation to be updated
+    #[must_use]
+    pub const fn with_screen_min_tilt(mut self, radians: f32) -> Self {
+        self.screen_min_tilt = radians;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_hysteresis(mut self, radians: f32) -> Self {
+        self.hysteresis = radians;
+        self
+    }
+
+    /// Number of samples a new state has to persist
+    #[must_use]
+    pub fn with_debounce(mut self, samples: u32) -> Self {
+        self.debounce = samples.max(1);
+        self
+    }
+
+    /// Current orientation, the screen starts as portrait
+    #[must_use]
+    pub const fn orientation(&self) -> DeviceOrientation {
+        DeviceOrientation {
+            face: self.face,
+            screen: self.screen,
+        }
+    }
+
+    /// Processes one accelerometer sample in g, returns the orientation if it changed
+    pub fn update(&mut self, accel: Vec3<f32>) -> Option<DeviceOrientation> {
+        let magnitude = accel.norm();
+        if (magnitude - 1.0).abs() > 0.3 {
+            self.pending_face.clear();
+            self.pending_screen.clear();
+            return None;
+        }
+        let up = accel / magnitude;
+
+        let mut changed = false;
+        match self.classify_face(up) {
+            Some(face) if self.face != Some(face) => {
+                if self.pending_face.update(face, self.debounce).is_some() {
+                    self.face = Some(face);
+                    changed = true;
+                }
+            }
+            _ => self.pending_face.clear(),
+        }
+        match self.classify_screen(up) {
+            Some(screen) if screen != self.screen => {
+                if self.pending_screen.update(screen, self.debounce).is_some() {
+                    self.screen = screen;
+                    changed = true;
+                }
+            }
+            _ => self.pending_screen.clear(),
+        }
+
+        if !changed {
+            return None;
+        }
+        let orientation = self.orientation();
+        (self.callback)(orientation);
+        Some(orientation)
+    }
+
+    // Face within the tilt tolerance, the current one until it is left by the
+    // hysteresis. None in between faces.
+    fn classify_face(&self, up: Vec3<f32>) -> Option<Face> {
+        let faces = [
+            (Face::XUp, up.x),
+            (Face::XDown, -up.x),
+            (Face::YUp, up.y),
+            (Face::YDown, -up.y),
+            (Face::ZUp, up.z),
+            (Face::ZDown, -up.z),
+        ];
+        if let Some(current) = self.face {
+            let limit = libm::cosf(self.tilt_tolerance + self.hysteresis);
+            if faces
+                .iter()
+                .any(|&(face, cos)| face == current && cos >= limit)
+            {
+                return Some(current);
+            }
+        }
+        let limit = libm::cosf(self.tilt_tolerance);
+        faces
+            .iter()
+            .find(|&&(_, cos)| cos >= limit)
+            .map(|&(face, _)| face)
+    }
+
+    // Screen orientation whose sector contains gravity in the X-Y plane, None
+    // while the plane is too flat or between sectors
+    fn classify_screen(&self, up: Vec3<f32>) -> Option<ScreenOrientation> {
+        if libm::hypotf(up.x, up.y) < libm::sinf(self.screen_min_tilt) {
+            return None;
+        }
+        let angle = libm::atan2f(up.x, up.y);
+        let distance = |screen: ScreenOrientation| {
+            let difference = (angle - screen.center()).abs();
+            difference.min(2.0 * PI - difference)
+        };
+        if distance(self.screen) <= PI / 4.0 + self.hysteresis / 2.0 {
+            return Some(self.screen);
+        }
+        [
+            ScreenOrientation::Portrait,
+            ScreenOrientation::LandscapeLeft,
+            ScreenOrientation::PortraitInverted,
+            ScreenOrientation::LandscapeRight,
+        ]
+        .into_iter()
+        .find(|&screen| distance(screen) <= PI / 4.0 - self.hysteresis / 2.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unit vector tilted `tilt` degrees from +Z towards the screen direction
+    // `angle` degrees, measured from +Y towards +X
+    fn up(tilt: f32, angle: f32) -> Vec3<f32> {
+        let (tilt, angle) = (tilt.to_radians(), angle.to_radians());
+        Vec3::new(
+            libm::sinf(tilt) * libm::sinf(angle),
+            libm::sinf(tilt) * libm::cosf(angle),
+            libm::cosf(tilt),
+        )
+    }
+
+    // Feeds `samples` copies of `accel`, returns the changes with the index of
+    // the sample that reported them
+    fn feed<F: FnMut(DeviceOrientation)>(
+        classifier: &mut OrientationClassifier<F>,
+        accel: Vec3<f32>,
+        samples: u32,
+    ) -> Option<(u32, DeviceOrientation)> {
+        let mut change = None;
+        for sample in 0..samples {
+            if let Some(orientation) = classifier.update(accel) {
+                assert!(change.is_none(), "reported twice");
+                change = Some((sample, orientation));
+            }
+        }
+        change
+    }
+
+    #[test]
+    fn debounces_face_changes_with_hysteresis() {
+        let mut classifier = OrientationClassifier::new(50.0);
+        let flat = DeviceOrientation {
+            face: Some(Face::ZUp),
+            screen: ScreenOrientation::Portrait,
+        };
+        assert_eq!(feed(&mut classifier, up(10.0, 0.0), 20), Some((9, flat)));
+        // Past the tilt tolerance but within the hysteresis, towards the
+        // current screen orientation
+        assert_eq!(feed(&mut classifier, up(35.0, 0.0), 20), None);
+        assert_eq!(classifier.orientation().face, Some(Face::ZUp));
+        // A short excursion to another face is filtered out
+        assert_eq!(feed(&mut classifier, up(90.0, 0.0), 5), None);
+        let upright = DeviceOrientation {
+            face: Some(Face::YUp),
+            screen: ScreenOrientation::Portrait,
+        };
+        assert_eq!(feed(&mut classifier, up(90.0, 0.0), 20), Some((4, upright)));
+        // Moving the device is ignored
+        assert_eq!(feed(&mut classifier, up(0.0, 0.0) * 1.5, 20), None);
+    }
+
+    #[test]
+    fn reports_screen_changes_before_a_face_is_known() {
+        let mut reported = None;
+        {
+            let mut classifier = OrientationClassifier::new(50.0)
+                .with_callback(|orientation| reported = Some(orientation));
+            // Held at 45º, between faces, then turned to landscape
+            assert_eq!(feed(&mut classifier, up(45.0, 0.0), 20), None);
+            let landscape = DeviceOrientation {
+                face: None,
+                screen: ScreenOrientation::LandscapeLeft,
+            };
+            assert_eq!(
+                feed(&mut classifier, up(45.0, 90.0), 20),
+                Some((9, landscape))
+            );
+            // Within the hysteresis of the current sector
+            assert_eq!(feed(&mut classifier, up(45.0, 45.0), 20), None);
+            feed(&mut classifier, up(45.0, -90.0), 20);
+        }
+        let landscape = DeviceOrientation {
+            face: None,
+            screen: ScreenOrientation::LandscapeRight,
+        };
+        assert_eq!(reported, Some(landscape));
+    }
+}
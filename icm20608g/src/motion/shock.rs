@@ -0,0 +1,333 @@
+use embedded_hal::i2c::I2c;
+
+use crate::sample::SampleScale;
+use crate::structs::{
+    AccelMeasurements, IntelligenceControl, InterruptEnable, ReadRegister, Vec3, WakeOnMotion,
+    WriteRegister,
+};
+
+// Resolution of the Wake-on-Motion threshold in g
+const WOM_THRESHOLD_LSB: f32 = 0.004;
+
+// A period during which the acceleration magnitude stayed beyond a threshold
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AccelEvent {
+    // Index of the first sample of the event, counted since the detector was
+    // created or reset
+    pub start: u64,
+    // Seconds
+    pub duration: f32,
+    // Most extreme magnitude in g: the lowest for a free fall, the highest for
+    // an impact
+    pub peak: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Episode {
+    start: u64,
+    samples: u32,
+    peak: f32,
+}
+
+// Tracks runs of samples that pass a magnitude test
+struct Episodes {
+    sample_rate_hz: f32,
+    min_samples: u32,
+    sample: u64,
+    current: Option<Episode>,
+}
+impl Episodes {
+    const fn new(sample_rate_hz: f32) -> Self {
+        Self {
+            sample_rate_hz,
+            min_samples: 1,
+            sample: 0,
+            current: None,
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn set_min_duration(&mut self, seconds: f32) {
+        self.min_samples = (libm::roundf(seconds * self.sample_rate_hz) as u32).max(1);
+    }
+
+    // Returns the event that ended with this sample, if it lasted long enough
+    #[allow(clippy::cast_precision_loss)]
+    fn update(
+        &mut self,
+        inside: bool,
+        magnitude: f32,
+        more_extreme: fn(f32, f32) -> f32,
+    ) -> Option<AccelEvent> {
+        let sample = self.sample;
+        self.sample += 1;
+        if inside {
+            let episode = self.current.get_or_insert(Episode {
+                start: sample,
+                samples: 0,
+                peak: magnitude,
+            });
+            episode.samples += 1;
+            episode.peak = more_extreme(episode.peak, magnitude);
+            return None;
+        }
+        let episode = self.current.take()?;
+        (episode.samples >= self.min_samples).then(|| AccelEvent {
+            start: episode.start,
+            duration: episode.samples as f32 / self.sample_rate_hz,
+            peak: episode.peak,
+        })
+    }
+
+    fn is_active(&self) -> bool {
+        self.current
+            .is_some_and(|episode| episode.samples >= self.min_samples)
+    }
+
+    fn reset(&mut self) {
+        self.sample = 0;
+        self.current = None;
+    }
+}
+
+// Detects free fall: the acceleration magnitude stays below `threshold` for at
+// least `min_duration`. Events are reported when the fall ends, `is_falling`
+// tells whether one is in progress.
+pub struct FreeFallDetector {
+    // g
+    threshold: f32,
+    episodes: Episodes,
+}
+impl FreeFallDetector {
+    #[must_use]
+    pub fn new(sample_rate_hz: f32) -> Self {
+        let mut episodes = Episodes::new(sample_rate_hz);
+        // A fall of about 5 cm
+        episodes.set_min_duration(0.1);
+        Self {
+            threshold: 0.3,
+            episodes,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    #[must_use]
+    pub fn with_min_duration(mut self, seconds: f32) -> Self {
+        self.episodes.set_min_duration(seconds);
+        self
+    }
+
+    /// True while the device has been falling for at least the minimum duration
+    #[must_use]
+    pub fn is_falling(&self) -> bool {
+        self.episodes.is_active()
+    }
+
+    pub fn reset(&mut self) {
+        self.episodes.reset();
+    }
+
+    /// Processes one accelerometer sample in g, returns the fall that just ended
+    pub fn update(&mut self, accel: Vec3<f32>) -> Option<AccelEvent> {
+        let magnitude = accel.norm();
+        self.episodes
+            .update(magnitude < self.threshold, magnitude, f32::min)
+    }
+
+    /// Processes a raw accelerometer sample
+    pub fn update_raw(
+        &mut self,
+        scale: &SampleScale,
+        accel: &AccelMeasurements,
+    ) -> Option<AccelEvent> {
+        self.update(scale.accel(accel))
+    }
+}
+
+// Detects shocks: the acceleration magnitude exceeds `threshold`. Events are
+// reported once the magnitude falls back below the threshold. The peak is
+// limited by the accelerometer full scale.
+pub struct ImpactDetector {
+    // g
+    threshold: f32,
+    episodes: Episodes,
+}
+impl ImpactDetector {
+    #[must_use]
+    pub const fn new(sample_rate_hz: f32) -> Self {
+        Self {
+            threshold: 3.0,
+            episodes: Episodes::new(sample_rate_hz),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Ignores shocks shorter than `seconds`, by default a single sample counts
+    #[must_use]
+    pub fn with_min_duration(mut self, seconds: f32) -> Self {
+        self.episodes.set_min_duration(seconds);
+        self
+    }
+
+    pub fn reset(&mut self) {
+        self.episodes.reset();
+    }
+
+    /// Processes one accelerometer sample in g, returns the impact that just ended
+    pub fn update(&mut self, accel: Vec3<f32>) -> Option<AccelEvent> {
+        let magnitude = accel.norm();
+        self.episodes
+            .update(magnitude > self.threshold, magnitude, f32::max)
+    }
+
+    /// Processes a raw accelerometer sample
+    pub fn update_raw(
+        &mut self,
+        scale: &SampleScale,
+        accel: &AccelMeasurements,
+    ) -> Option<AccelEvent> {
+        self.update(scale.accel(accel))
+    }
+}
+
+/// Configures the Wake-on-Motion logic to raise the interrupt pin when any axis
+/// changes by more than `threshold` g (at most 1.02 g) between two samples, so the
+/// host can sleep until a fall or shock starts. The threshold has a resolution of 4 mg.
+///
+/// Only the threshold, the interrupt and the detection logic are configured. For the
+/// accelerometer to sample while the rest of the device sleeps the caller must also
+/// select the wake-up rate with `LowPowerModeConf::lposc_clksel` and enable
+/// `PowerManagement1::accel_cycle`.
+///
+/// # Errors
+/// Will error if unable to communicate with the device
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn arm_wake_on_motion<I: I2c>(i2c: &mut I, threshold: f32) -> Result<(), I::Error> {
+    let wom_thr = libm::roundf(threshold / WOM_THRESHOLD_LSB).clamp(0.0, 255.0) as u8;
+    WakeOnMotion { wom_thr }.write(i2c)?;
+    let mut interrupts = InterruptEnable::new(i2c)?;
+    interrupts.wom_int_en = true;
+    interrupts.write(i2c)?;
+    IntelligenceControl {
+        accel_intel_en: true,
+        accel_intel_mode: true,
+    }
+    .write(i2c)
+}
+
+/// Disables the Wake-on-Motion logic and its interrupt
+///
+/// # Errors
+/// Will error if unable to communicate with the device
+pub fn disarm_wake_on_motion<I: I2c>(i2c: &mut I) -> Result<(), I::Error> {
+    IntelligenceControl {
+        accel_intel_en: false,
+        accel_intel_mode: false,
+    }
+    .write(i2c)?;
+    let mut interrupts = InterruptEnable::new(i2c)?;
+    interrupts.wom_int_en = false;
+    interrupts.write(i2c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeDevice;
+
+    const STILL: Vec3<f32> = Vec3::new(0.0, 0.0, 1.0);
+
+    #[test]
+    fn free_fall_reports_start_duration_and_lowest_magnitude() {
+        let mut detector = FreeFallDetector::new(100.0);
+        for _ in 0..5 {
+            assert_eq!(detector.update(STILL), None);
+        }
+        for i in 0..20 {
+            let magnitude = if i == 7 { 0.05 } else { 0.1 };
+            assert_eq!(detector.update(STILL * magnitude), None);
+            // The default minimum duration is 0.1 s
+            assert_eq!(detector.is_falling(), i >= 9);
+        }
+        let event = detector.update(STILL).unwrap();
+        assert_eq!(event.start, 5);
+        assert!((event.duration - 0.2).abs() < 1e-6);
+        assert!((event.peak - 0.05).abs() < 1e-6);
+        assert!(!detector.is_falling());
+    }
+
+    #[test]
+    fn short_falls_are_ignored() {
+        let mut detector = FreeFallDetector::new(100.0).with_min_duration(0.05);
+        for _ in 0..4 {
+            detector.update(Vec3::default());
+        }
+        assert_eq!(detector.update(STILL), None);
+        for _ in 0..5 {
+            detector.update(Vec3::default());
+        }
+        let event = detector.update(STILL).unwrap();
+        assert_eq!(event.start, 5);
+    }
+
+    #[test]
+    fn impacts_shorter_than_the_minimum_are_ignored() {
+        let mut detector = ImpactDetector::new(1_000.0).with_min_duration(0.003);
+        for magnitude in [1.0, 5.0, 6.0, 1.0] {
+            assert_eq!(detector.update(STILL * magnitude), None);
+        }
+        for magnitude in [4.0, 9.5, 7.0, 3.5] {
+            assert_eq!(detector.update(STILL * magnitude), None);
+        }
+        let event = detector.update(STILL).unwrap();
+        assert_eq!(event.start, 4);
+        assert!((event.duration - 0.004).abs() < 1e-6);
+        assert!((event.peak - 9.5).abs() < 1e-6);
+
+        detector.reset();
+        detector.update(STILL * 4.0);
+        detector.update(STILL * 4.0);
+        detector.update(STILL * 4.0);
+        assert_eq!(detector.update(STILL).unwrap().start, 0);
+    }
+
+    #[test]
+    fn wake_on_motion_threshold_is_rounded_and_clamped() {
+        for (threshold, wom_thr) in [
+            (0.1, 25),
+            (0.005, 1),
+            (0.007, 2),
+            (1.02, 255),
+            (2.0, 255),
+            (-1.0, 0),
+        ] {
+            let mut device = FakeDevice::new();
+            arm_wake_on_motion(&mut device, threshold).unwrap();
+            assert_eq!(device.registers[0x1F], wom_thr, "{threshold}");
+        }
+    }
+
+    #[test]
+    fn wake_on_motion_keeps_other_interrupts() {
+        let mut device = FakeDevice::new();
+        // Data ready interrupt already enabled
+        device.registers[0x38] = 1;
+        arm_wake_on_motion(&mut device, 0.1).unwrap();
+        assert_eq!(device.registers[0x38], 0b1110_0001);
+        assert_eq!(device.registers[0x69], 0b1100_0000);
+
+        disarm_wake_on_motion(&mut device).unwrap();
+        assert_eq!(device.registers[0x38], 1);
+        assert_eq!(device.registers[0x69], 0);
+    }
+}
@@ -0,0 +1,259 @@
+use embedded_hal::i2c::I2c;
+
+use crate::calibration::{self, AxisStats};
+use crate::sample::ImuSample;
+use crate::structs::{GyroOffset, Vec3};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MotionState {
+    Moving,
+    Still,
+}
+
+// Zero motion detection with gyroscope bias tracking.
+//
+// Samples are collected in consecutive windows. A window in which the standard
+// deviation of every accelerometer and gyroscope axis stays below its threshold
+// is still, any other window is moving. The mean gyroscope rate of every still
+// window is blended into the bias estimate, so a slowly drifting bias is followed
+// over a long mission. `commit_bias` moves the estimate into `GyroOffset`.
+pub struct StationaryDetector {
+    // Samples per window
+    window: u32,
+    // Largest standard deviation in g
+    accel_threshold: f32,
+    // Largest standard deviation in rad/s
+    gyro_threshold: f32,
+    // Weight of a still window in the bias estimate
+    bias_weight: f32,
+
+    accel: AxisStats,
+    gyro: AxisStats,
+    state: MotionState,
+    // Gyroscope bias in rad/s, None before the first still window
+    bias: Option<Vec3<f32>>,
+}
+impl StationaryDetector {
+    /// Detector with windows of half a second
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn new(sample_rate_hz: f32) -> Self {
+        Self {
+            window: (libm::roundf(sample_rate_hz / 2.0) as u32).max(2),
+            accel_threshold: 0.01,
+            gyro_threshold: 0.2_f32.to_radians(),
+            bias_weight: 0.1,
+            accel: AxisStats::default(),
+            gyro: AxisStats::default(),
+            state: MotionState::Moving,
+            bias: None,
+        }
+    }
+
+    /// Number of samples evaluated together, at least 2
+    #[must_use]
+    pub fn with_window(mut self, samples: u32) -> Self {
+        self.window = samples.max(2);
+        self
+    }
+
+    /// Largest standard deviations of the accelerometer in g and the gyroscope in
+    /// rad/s that are still
+    #[must_use]
+    pub const fn with_thresholds(mut self, accel: f32, gyro: f32) -> Self {
+        self.accel_threshold = accel;
+        self.gyro_threshold = gyro;
+        self
+    }
+
+    /// Weight between 0 and 1 of every still window in the bias estimate
+    #[must_use]
+    pub const fn with_bias_weight(mut self, weight: f32) -> Self {
+        self.bias_weight = weight;
+        self
+    }
+
+    #[must_use]
+    pub const fn state(&self) -> MotionState {
+        self.state
+    }
+
+    #[must_use]
+    pub fn is_still(&self) -> bool {
+        self.state == MotionState::Still
+    }
+
+    /// Estimated gyroscope bias in rad/s, zero before the device was still once
+    #[must_use]
+    pub fn bias(&self) -> Vec3<f32> {
+        self.bias.unwrap_or_default()
+    }
+
+    /// Removes the estimated bias from a rate in rad/s
+    #[must_use]
+    pub fn correct(&self, gyro: Vec3<f32>) -> Vec3<f32> {
+        gyro - self.bias()
+    }
+
+    /// Processes one sample, returns the new state when it changed
+    pub fn update(&mut self, sample: &ImuSample) -> Option<MotionState> {
+        self.accel
+            .push([sample.accel.x, sample.accel.y, sample.accel.z]);
+        self.gyro
+            .push([sample.gyro.x, sample.gyro.y, sample.gyro.z]);
+        if self.accel.count() < self.window {
+            return None;
+        }
+
+        let below = |stats: &AxisStats, threshold: f32| {
+            stats
+                .variance()
+                .iter()
+                .all(|&variance| variance <= threshold * threshold)
+        };
+        let still =
+            below(&self.accel, self.accel_threshold) && below(&self.gyro, self.gyro_threshold);
+        if still {
+            let [x, y, z] = self.gyro.mean();
+            let mean = Vec3::new(x, y, z);
+            self.bias = Some(match self.bias {
+                Some(bias) => bias + (mean - bias) * self.bias_weight,
+                None => mean,
+            });
+        }
+        self.accel = AxisStats::default();
+        self.gyro = AxisStats::default();
+
+        let state = if still {
+            MotionState::Still
+        } else {
+            MotionState::Moving
+        };
+        (state != self.state).then(|| {
+            self.state = state;
+            state
+        })
+    }
+
+    /// Moves the estimated bias into `GyroOffset` so the device outputs bias free
+    /// rates and restarts the estimate from zero. Returns the offsets written.
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn commit_bias<I: I2c>(&mut self, i2c: &mut I) -> Result<GyroOffset, I::Error> {
+        let offset = calibration::commit_bias(i2c, self.bias())?;
+        if self.bias.is_some() {
+            self.bias = Some(Vec3::default());
+        }
+        Ok(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{ReadRegister, WriteRegister};
+    use crate::test_support::{FakeDevice, Noise};
+
+    const WINDOW: u32 = 10;
+
+    fn still(gyro: Vec3<f32>) -> ImuSample {
+        ImuSample {
+            accel: Vec3::new(0.0, 0.0, 1.0),
+            gyro,
+        }
+    }
+
+    // Feeds one window and returns the state change reported at its end
+    fn window(
+        detector: &mut StationaryDetector,
+        mut sample: impl FnMut() -> ImuSample,
+    ) -> Option<MotionState> {
+        for _ in 1..WINDOW {
+            assert_eq!(detector.update(&sample()), None);
+        }
+        detector.update(&sample())
+    }
+
+    fn assert_close(a: Vec3<f32>, b: Vec3<f32>) {
+        assert!((a - b).norm() < 1e-6, "{a:?} vs {b:?}");
+    }
+
+    #[test]
+    fn switches_between_moving_and_still() {
+        let mut detector = StationaryDetector::new(100.0).with_window(WINDOW);
+        let mut noise = Noise::new(11);
+        let mut moving = || ImuSample {
+            accel: Vec3::new(0.1 * noise.normal(), 0.1 * noise.normal(), 1.0),
+            gyro: Vec3::new(0.0, 0.5 * noise.normal(), 0.0),
+        };
+        assert_eq!(detector.state(), MotionState::Moving);
+        assert_eq!(window(&mut detector, &mut moving), None);
+        assert_eq!(
+            window(&mut detector, || still(Vec3::default())),
+            Some(MotionState::Still)
+        );
+        assert!(detector.is_still());
+        assert_eq!(window(&mut detector, || still(Vec3::default())), None);
+        assert_eq!(
+            window(&mut detector, &mut moving),
+            Some(MotionState::Moving)
+        );
+        assert!(!detector.is_still());
+    }
+
+    #[test]
+    fn averages_bias_over_still_windows() {
+        let mut detector = StationaryDetector::new(100.0)
+            .with_window(WINDOW)
+            .with_bias_weight(0.5);
+        assert_close(detector.bias(), Vec3::default());
+
+        let first = Vec3::new(0.01, -0.02, 0.004);
+        window(&mut detector, || still(first));
+        assert_close(detector.bias(), first);
+
+        let second = Vec3::new(0.02, -0.01, 0.0);
+        window(&mut detector, || still(second));
+        assert_close(detector.bias(), Vec3::new(0.015, -0.015, 0.002));
+        assert_close(detector.correct(second), Vec3::new(0.005, 0.005, -0.002));
+
+        // Moving windows leave the estimate alone
+        let mut toggle = false;
+        window(&mut detector, || {
+            toggle = !toggle;
+            still(Vec3::new(if toggle { 1.0 } else { -1.0 }, 0.0, 0.0))
+        });
+        assert_close(detector.bias(), Vec3::new(0.015, -0.015, 0.002));
+    }
+
+    #[test]
+    fn commit_bias_restarts_the_estimate() {
+        let mut device = FakeDevice::new();
+        GyroOffset {
+            xg_offs: 100,
+            yg_offs: -100,
+            zg_offs: 0,
+        }
+        .write(&mut device)
+        .unwrap();
+        let mut detector = StationaryDetector::new(100.0)
+            .with_window(WINDOW)
+            .with_bias_weight(0.5);
+        let bias = Vec3::new(1.0_f32.to_radians(), -0.5_f32.to_radians(), 0.0);
+        window(&mut detector, || still(bias));
+
+        let written = detector.commit_bias(&mut device).unwrap();
+        assert_eq!(
+            (written.xg_offs, written.yg_offs, written.zg_offs),
+            (67, -84, 0)
+        );
+        assert_eq!(GyroOffset::new(&mut device).unwrap(), written);
+        assert_close(detector.bias(), Vec3::default());
+
+        // The residual left by the offsets is blended in from zero
+        let residual = Vec3::new(0.002, 0.0, 0.0);
+        window(&mut detector, || still(residual));
+        assert_close(detector.bias(), residual * 0.5);
+    }
+}
@@ -0,0 +1,287 @@
+use super::Axis;
+use crate::fifo::FifoFrame;
+use crate::sample::SampleScale;
+use crate::structs::Vec3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TapKind {
+    Single,
+    Double,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TapEvent {
+    pub kind: TapKind,
+    // Axis with the largest acceleration peak of the (first) tap
+    pub axis: Axis,
+    // Whether the peak was along the positive direction of `axis`
+    pub positive: bool,
+    // Index of the sample the (first) tap started on, counted since the
+    // detector was created or reset
+    pub sample: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Peak {
+    start: u64,
+    axis: Axis,
+    value: f32,
+}
+
+// Software tap and double tap detection on accelerometer samples.
+//
+// A tap is a spike of the acceleration, with gravity and slow motion removed,
+// above `threshold` that is over within `max_duration`. Activity during the
+// `quiet` time after a tap is ignored, so the ringing of the impact is not
+// taken for another tap. A second tap starting within `double_tap_window` after
+// the quiet time makes a double tap. A single tap is only reported once that
+// window passed, when motion too long for a tap starts in it, or immediately if
+// the window is zero.
+//
+// Needs an output data rate of at least 400 Hz to resolve the spike. Samples
+// must be evenly spaced, e.g. read from the FIFO with `process`.
+pub struct TapDetector {
+    sample_rate_hz: f32,
+    // g
+    threshold: f32,
+    // The following are in samples
+    max_duration: u32,
+    quiet: u32,
+    double_tap_window: u32,
+    // Weight of a new sample in the gravity estimate
+    baseline_weight: f32,
+
+    baseline: Option<Vec3<f32>>,
+    sample: u64,
+    peak: Option<Peak>,
+    // Set while waiting for motion that was too long for a tap to end
+    rejecting: bool,
+    quiet_until: u64,
+    // First tap waiting for a second one, and the end of its window
+    pending: Option<(TapEvent, u64)>,
+}
+impl TapDetector {
+    #[must_use]
+    pub fn new(sample_rate_hz: f32) -> Self {
+        let mut detector = Self {
+            sample_rate_hz,
+            threshold: 1.5,
+            max_duration: 0,
+            quiet: 0,
+            double_tap_window: 0,
+            // Gravity estimate with a time constant of 0.1 s
+            baseline_weight: (10.0 / sample_rate_hz).min(1.0),
+            baseline: None,
+            sample: 0,
+            peak: None,
+            rejecting: false,
+            quiet_until: 0,
+            pending: None,
+        };
+        detector.max_duration = detector.samples(0.06);
+        detector.quiet = detector.samples(0.05);
+        detector.double_tap_window = detector.samples(0.3);
+        detector
+    }
+
+    /// Acceleration in g above the gravity estimate that starts a tap
+    #[must_use]
+    pub const fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Longest spike in seconds that still counts as a tap
+    #[must_use]
+    pub fn with_max_duration(mut self, seconds: f32) -> Self {
+        self.max_duration = self.samples(seconds);
+        self
+    }
+
+    /// Time in seconds after a tap during which activity is ignored
+    #[must_use]
+    pub fn with_quiet_time(mut self, seconds: f32) -> Self {
+        self.quiet = self.samples(seconds);
+        self
+    }
+
+    /// Time in seconds after the quiet time in which a second tap makes a double
+    /// tap, zero reports every tap as a single tap without delay
+    #[must_use]
+    pub fn with_double_tap_window(mut self, seconds: f32) -> Self {
+        self.double_tap_window = self.samples(seconds);
+        self
+    }
+
+    /// Forgets all state and restarts the sample count
+    pub fn reset(&mut self) {
+        self.baseline = None;
+        self.sample = 0;
+        self.peak = None;
+        self.rejecting = false;
+        self.quiet_until = 0;
+        self.pending = None;
+    }
+
+    /// Processes one accelerometer sample in g
+    pub fn update(&mut self, accel: Vec3<f32>) -> Option<TapEvent> {
+        let sample = self.sample;
+        self.sample += 1;
+        let baseline = *self.baseline.get_or_insert(accel);
+
+        let mut event = None;
+        if self.peak.is_none() {
+            if let Some((first, window_end)) = self.pending {
+                if sample > window_end {
+                    self.pending = None;
+                    event = Some(first);
+                }
+            }
+        }
+        if sample < self.quiet_until {
+            return event;
+        }
+
+        let delta = accel - baseline;
+        let (axis, value) = Axis::largest(delta);
+        let active = value.abs() > self.threshold;
+        if self.rejecting {
+            if !active {
+                self.rejecting = false;
+                self.quiet_until = sample + u64::from(self.quiet);
+            }
+            return event;
+        }
+        match &mut self.peak {
+            Some(peak) if active => {
+                if value.abs() > peak.value.abs() {
+                    peak.axis = axis;
+                    peak.value = value;
+                }
+                if sample - peak.start > u64::from(self.max_duration) {
+                    // Too long for a tap, wait for the motion to end. A tap
+                    // before it can no longer become a double tap.
+                    self.peak = None;
+                    self.rejecting = true;
+                    event = self.pending.take().map(|(first, _)| first);
+                }
+            }
+            Some(peak) => {
+                let tap = TapEvent {
+                    kind: TapKind::Single,
+                    axis: peak.axis,
+                    positive: peak.value > 0.0,
+                    sample: peak.start,
+                };
+                self.peak = None;
+                self.quiet_until = sample + u64::from(self.quiet);
+                if let Some((first, _)) = self.pending.take() {
+                    event = Some(TapEvent {
+                        kind: TapKind::Double,
+                        ..first
+                    });
+                } else if self.double_tap_window == 0 {
+                    event = Some(tap);
+                } else {
+                    self.pending =
+                        Some((tap, self.quiet_until + u64::from(self.double_tap_window)));
+                }
+            }
+            None if active => {
+                self.peak = Some(Peak {
+                    start: sample,
+                    axis,
+                    value,
+                });
+            }
+            None => {
+                self.baseline = Some(baseline + delta * self.baseline_weight);
+            }
+        }
+        event
+    }
+
+    /// Processes the accelerometer samples of a FIFO batch in order and writes the
+    /// detected taps to `out`. Returns the number of events written, events that
+    /// do not fit are dropped.
+    pub fn process(
+        &mut self,
+        scale: &SampleScale,
+        frames: &[FifoFrame],
+        out: &mut [TapEvent],
+    ) -> usize {
+        let mut written = 0;
+        for accel in frames.iter().filter_map(|frame| frame.accel.as_ref()) {
+            if let Some(event) = self.update(scale.accel(accel)) {
+                if let Some(slot) = out.get_mut(written) {
+                    *slot = event;
+                    written += 1;
+                }
+            }
+        }
+        written
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn samples(&self, seconds: f32) -> u32 {
+        libm::roundf((seconds * self.sample_rate_hz).max(0.0)) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds 1 g on Z, with `spikes` of 4 g as (first sample, length), and
+    // returns the events with the sample they were reported on
+    fn run(
+        detector: &mut TapDetector,
+        samples: u64,
+        spikes: &[(u64, u64)],
+    ) -> [Option<(u64, TapEvent)>; 4] {
+        let mut events = [None; 4];
+        let mut written = 0;
+        for sample in 0..samples {
+            let spike = spikes
+                .iter()
+                .any(|&(start, length)| (start..start + length).contains(&sample));
+            let z = if spike { 4.0 } else { 1.0 };
+            if let Some(event) = detector.update(Vec3::new(0.0, 0.0, z)) {
+                events[written] = Some((sample, event));
+                written += 1;
+            }
+        }
+        events
+    }
+
+    fn tap(kind: TapKind, sample: u64) -> TapEvent {
+        TapEvent {
+            kind,
+            axis: Axis::Z,
+            positive: true,
+            sample,
+        }
+    }
+
+    #[test]
+    fn reports_single_and_double_taps() {
+        // 24 samples max duration, 20 quiet, 120 double tap window
+        let mut detector = TapDetector::new(400.0);
+        let events = run(&mut detector, 400, &[(100, 5)]);
+        assert_eq!(events[0], Some((246, tap(TapKind::Single, 100))));
+        assert_eq!(events[1], None);
+
+        detector.reset();
+        let events = run(&mut detector, 400, &[(100, 5), (150, 5)]);
+        assert_eq!(events[0], Some((155, tap(TapKind::Double, 100))));
+        assert_eq!(events[1], None);
+    }
+
+    #[test]
+    fn long_motion_after_a_tap_reports_the_tap() {
+        let mut detector = TapDetector::new(400.0);
+        let events = run(&mut detector, 600, &[(100, 5), (150, 100)]);
+        assert_eq!(events[0], Some((175, tap(TapKind::Single, 100))));
+        assert_eq!(events[1], None);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::gravity::STANDARD_GRAVITY;
+use crate::orientation::Quaternion;
+use crate::sample::ImuSample;
+use crate::structs::Vec3;
+
+mod stance;
+pub use stance::StanceDetector;
+
+// How well the zero velocity updates bound the drift, velocities in m/s
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DriftStatistics {
+    pub zero_velocity_updates: u32,
+    // Velocity removed by the latest update
+    pub last_velocity_error: Vec3<f32>,
+    // Largest and RMS magnitude of the removed velocity over all updates
+    pub max_velocity_error: f32,
+    pub rms_velocity_error: f32,
+    // Seconds integrated since the latest update
+    pub time_since_update: f32,
+}
+
+// Body frame increments of the previous step, for the sculling correction
+#[derive(Clone, Copy, Debug)]
+struct Previous {
+    attitude: Quaternion,
+    sample: ImuSample,
+    rotation: Vec3<f32>,
+    velocity: Vec3<f32>,
+}
+
+// Strapdown integration of specific force into velocity and position in the
+// earth frame (Z up), in m and m/s.
+//
+// Attitude comes from one of the filters in `orientation`, which already
+// account for coning. Each step integrates the mean of two consecutive samples,
+// rotates the velocity increment with the attitude at the start of the step and
+// applies the rotation and sculling corrections for the body turning during the
+// step. Gravity is removed after rotating into the earth frame.
+//
+// Unaided integration drifts quickly. Zero velocity updates, e.g. whenever a
+// `StanceDetector` fires on a foot mounted sensor, reset the velocity and remove
+// the position error accumulated since the previous update, assuming the
+// velocity error grew linearly.
+#[derive(Clone, Debug, Default)]
+pub struct Strapdown {
+    position: Vec3<f32>,
+    velocity: Vec3<f32>,
+    previous: Option<Previous>,
+    drift: DriftStatistics,
+    // Sum of squared velocity errors, for the RMS
+    squared_errors: f32,
+}
+impl Strapdown {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts at a known position in m
+    #[must_use]
+    pub const fn with_position(mut self, position: Vec3<f32>) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// Starts with a known velocity in m/s
+    #[must_use]
+    pub const fn with_velocity(mut self, velocity: Vec3<f32>) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    #[must_use]
+    pub const fn position(&self) -> Vec3<f32> {
+        self.position
+    }
+
+    #[must_use]
+    pub const fn velocity(&self) -> Vec3<f32> {
+        self.velocity
+    }
+
+    #[must_use]
+    pub const fn drift(&self) -> &DriftStatistics {
+        &self.drift
+    }
+
+    /// Returns to the origin at rest and clears the statistics
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Integrates a sample taken `dt` seconds after the previous one, with the
+    /// attitude estimated for it. The first sample only initializes the state.
+    /// A zero velocity update follows if `stance` is set.
+    pub fn update(&mut self, attitude: &Quaternion, sample: &ImuSample, dt: f32, stance: bool) {
+        if let Some(previous) = self.previous {
+            // Body frame increments over the step, trapezoidal
+            let rotation = (previous.sample.gyro + sample.gyro) * (0.5 * dt);
+            let velocity = (previous.sample.accel + sample.accel) * (0.5 * STANDARD_GRAVITY * dt);
+            let sculling =
+                (previous.rotation.cross(velocity) + previous.velocity.cross(rotation)) / 12.0;
+            let corrected = velocity + rotation.cross(velocity) * 0.5 + sculling;
+
+            let increment =
+                previous.attitude.rotate(corrected) - Vec3::new(0.0, 0.0, STANDARD_GRAVITY * dt);
+            let velocity_before = self.velocity;
+            self.velocity += increment;
+            self.position += (velocity_before + self.velocity) * (0.5 * dt);
+            self.drift.time_since_update += dt;
+            self.previous = Some(Previous {
+                attitude: *attitude,
+                sample: *sample,
+                rotation,
+                velocity,
+            });
+        } else {
+            self.previous = Some(Previous {
+                attitude: *attitude,
+                sample: *sample,
+                rotation: Vec3::default(),
+                velocity: Vec3::default(),
+            });
+        }
+        if stance {
+            self.zero_velocity_update();
+        }
+    }
+
+    /// Applies a zero velocity update: the device is known to be at rest
+    #[allow(clippy::cast_precision_loss)]
+    pub fn zero_velocity_update(&mut self) {
+        let error = self.velocity;
+        // A linearly growing velocity error displaced the position by half of
+        // its final value times the elapsed time
+        self.position -= error * (0.5 * self.drift.time_since_update);
+        self.velocity = Vec3::default();
+
+        let magnitude = error.norm();
+        let drift = &mut self.drift;
+        drift.zero_velocity_updates += 1;
+        drift.last_velocity_error = error;
+        drift.max_velocity_error = drift.max_velocity_error.max(magnitude);
+        self.squared_errors += magnitude * magnitude;
+        drift.rms_velocity_error =
+            libm::sqrtf(self.squared_errors / drift.zero_velocity_updates as f32);
+        drift.time_since_update = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 100.0;
+    const DT: f32 = 1.0 / RATE;
+
+    fn level(accel_x: f32) -> ImuSample {
+        ImuSample {
+            accel: Vec3::new(accel_x, 0.0, 1.0),
+            gyro: Vec3::default(),
+        }
+    }
+
+    #[test]
+    fn integrates_constant_acceleration() {
+        let mut strapdown = Strapdown::new().with_position(Vec3::new(1.0, 2.0, 3.0));
+        for _ in 0..=100 {
+            strapdown.update(&Quaternion::IDENTITY, &level(0.1), DT, false);
+        }
+        let velocity = strapdown.velocity();
+        let position = strapdown.position() - Vec3::new(1.0, 2.0, 3.0);
+        assert!(
+            (velocity - Vec3::new(0.980_665, 0.0, 0.0)).norm() < 1e-4,
+            "{velocity:?}"
+        );
+        assert!(
+            (position - Vec3::new(0.490_33, 0.0, 0.0)).norm() < 1e-3,
+            "{position:?}"
+        );
+    }
+
+    #[test]
+    fn removes_gravity_in_any_attitude() {
+        let attitude = Quaternion::from_axis_angle(Vec3::new(1.0, 0.3, 0.0), 1.2);
+        let sample = ImuSample {
+            accel: attitude.rotate_inverse(Vec3::new(0.0, 0.0, 1.0)),
+            gyro: Vec3::default(),
+        };
+        let mut strapdown = Strapdown::new();
+        for _ in 0..=100 {
+            strapdown.update(&attitude, &sample, DT, false);
+        }
+        assert!(strapdown.velocity().norm() < 1e-4);
+        assert!(strapdown.position().norm() < 1e-4);
+    }
+
+    #[test]
+    fn zero_velocity_update_removes_linear_drift() {
+        let mut strapdown = Strapdown::new();
+        // 0.01 g accelerometer bias while standing still for 2 s
+        for _ in 0..=200 {
+            strapdown.update(&Quaternion::IDENTITY, &level(0.01), DT, false);
+        }
+        assert!((strapdown.position().x - 0.196).abs() < 1e-3);
+        strapdown.zero_velocity_update();
+        assert!(strapdown.position().norm() < 1e-4);
+        assert_eq!(strapdown.velocity(), Vec3::default());
+        let drift = strapdown.drift();
+        assert_eq!(drift.zero_velocity_updates, 1);
+        assert!((drift.last_velocity_error.x - 0.196).abs() < 1e-3);
+        assert!((drift.rms_velocity_error - drift.max_velocity_error).abs() < 1e-6);
+        assert!(drift.time_since_update.abs() < f32::EPSILON);
+    }
+
+    // Four 1 m steps: 0.5 s swing accelerating and braking at 1.63 g, then
+    // 0.5 s stance. Returns the position error with a 0.02 g bias.
+    fn walk(zero_velocity_updates: bool) -> f32 {
+        let mut strapdown = Strapdown::new();
+        let mut stance = StanceDetector::new(RATE);
+        strapdown.update(&Quaternion::IDENTITY, &level(0.02), DT, false);
+        for _ in 0..4 {
+            for n in 0..100 {
+                let accel = match n {
+                    0..25 => 16.0 / STANDARD_GRAVITY,
+                    25..50 => -16.0 / STANDARD_GRAVITY,
+                    _ => 0.0,
+                };
+                let sample = level(accel + 0.02);
+                let resting = stance.update(&sample) && zero_velocity_updates;
+                strapdown.update(&Quaternion::IDENTITY, &sample, DT, resting);
+            }
+        }
+        (strapdown.position() - Vec3::new(4.0, 0.0, 0.0)).norm()
+    }
+
+    #[test]
+    fn stance_updates_bound_the_position_error() {
+        let unaided = walk(false);
+        let aided = walk(true);
+        assert!(unaided > 1.0, "{unaided}");
+        assert!(aided < 0.1, "{aided}");
+    }
+}
@@ -0,0 +1,51 @@
+use crate::sample::ImuSample;
+
+// Detects the stance phase of a foot mounted sensor: the acceleration stays
+// within `accel_tolerance` of 1 g and the angular rate below `gyro_threshold`
+// for `min_samples` samples in a row.
+pub struct StanceDetector {
+    // g
+    accel_tolerance: f32,
+    // rad/s
+    gyro_threshold: f32,
+    min_samples: u32,
+    count: u32,
+}
+impl StanceDetector {
+    /// Detector needing 50 ms of rest
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn new(sample_rate_hz: f32) -> Self {
+        Self {
+            accel_tolerance: 0.05,
+            gyro_threshold: 0.5,
+            min_samples: (libm::roundf(sample_rate_hz * 0.05) as u32).max(1),
+            count: 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_thresholds(mut self, accel_tolerance: f32, gyro_threshold: f32) -> Self {
+        self.accel_tolerance = accel_tolerance;
+        self.gyro_threshold = gyro_threshold;
+        self
+    }
+
+    #[must_use]
+    pub fn with_min_samples(mut self, samples: u32) -> Self {
+        self.min_samples = samples.max(1);
+        self
+    }
+
+    /// Processes one sample, true while in stance
+    pub fn update(&mut self, sample: &ImuSample) -> bool {
+        let resting = (sample.accel.norm() - 1.0).abs() <= self.accel_tolerance
+            && sample.gyro.norm() <= self.gyro_threshold;
+        self.count = if resting {
+            self.count.saturating_add(1)
+        } else {
+            0
+        };
+        self.count >= self.min_samples
+    }
+}
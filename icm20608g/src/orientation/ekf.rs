@@ -0,0 +1,350 @@
+use core::f32::consts::PI;
+
+use embedded_hal::i2c::I2c;
+
+use super::Quaternion;
+use crate::sample::ImuSample;
+use crate::structs::{AccelConfig1, AccelConfig2, Config, GyroConfig, ReadRegister, Vec3};
+
+// Gyroscope rate noise spectral density from the datasheet, º/s/√Hz
+const GYRO_NOISE_DENSITY: f32 = 0.008;
+// Accelerometer noise spectral density from the datasheet, g/√Hz
+const ACCEL_NOISE_DENSITY: f32 = 100e-6;
+// Not specified by the datasheet, a conservative default in rad/s/√s
+const GYRO_BIAS_WALK: f32 = 1e-4;
+// Initial zero rate output tolerance from the datasheet, º/s
+const GYRO_BIAS_TOLERANCE: f32 = 5.0;
+// Initial attitude uncertainty in rad
+const INITIAL_ATTITUDE_STD: f32 = PI / 4.0;
+
+// Noise model of `AttitudeEkf`, as standard deviations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EkfNoise {
+    // Gyroscope rate noise per sample in rad/s
+    pub gyro: f32,
+    // Gyroscope bias random walk in rad/s/√s
+    pub gyro_bias_walk: f32,
+    // Accelerometer noise per sample in g
+    pub accel: f32,
+}
+impl EkfNoise {
+    /// Datasheet noise for the configured filters and full scales. The white noise
+    /// density is integrated over the filter bandwidth and the quantization noise
+    /// of one LSB is added.
+    #[must_use]
+    pub fn new(
+        config: &Config,
+        gyro_config: &GyroConfig,
+        accel_config: &AccelConfig1,
+        accel_config2: &AccelConfig2,
+    ) -> Self {
+        let noise = |density: f32, bandwidth: f32, lsb: f32| {
+            libm::sqrtf(density * density * bandwidth + lsb * lsb / 12.0)
+        };
+        let gyro = noise(
+            GYRO_NOISE_DENSITY,
+            gyro_config.bandwidth_hz(config),
+            1.0 / gyro_config.sensitivity(),
+        );
+        Self {
+            gyro: gyro.to_radians(),
+            gyro_bias_walk: GYRO_BIAS_WALK,
+            accel: noise(
+                ACCEL_NOISE_DENSITY,
+                accel_config2.bandwidth_hz(),
+                1.0 / accel_config.sensitivity(),
+            ),
+        }
+    }
+
+    /// Reads the filter and full scale configuration from the device
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn read<I: I2c>(i2c: &mut I) -> Result<Self, I::Error> {
+        Ok(Self::new(
+            &Config::new(i2c)?,
+            &GyroConfig::new(i2c)?,
+            &AccelConfig1::new(i2c)?,
+            &AccelConfig2::new(i2c)?,
+        ))
+    }
+}
+
+// Error-state extended Kalman filter for attitude and gyroscope bias.
+//
+// The nominal state is the orientation quaternion and the gyroscope bias. The
+// filter tracks the covariance of a six element error state: a small rotation
+// in the sensor frame followed by the bias error. Gyroscope rates propagate the
+// state, the direction of the measured acceleration corrects it as an
+// observation of gravity. Heading and the bias about the vertical axis are not
+// observable, their variance grows over time.
+pub struct AttitudeEkf {
+    noise: EkfNoise,
+    // Samples whose magnitude differs from 1 g by more than this are not used
+    // as gravity observations
+    gravity_tolerance: f32,
+    orientation: Quaternion,
+    // Gyroscope bias in rad/s
+    bias: Vec3<f32>,
+    // Error state covariance, attitude in rad² then bias in (rad/s)²
+    covariance: [[f32; 6]; 6],
+}
+impl AttitudeEkf {
+    #[must_use]
+    pub fn new(noise: EkfNoise) -> Self {
+        let mut covariance = [[0.0; 6]; 6];
+        let bias_std = GYRO_BIAS_TOLERANCE.to_radians();
+        for (i, row) in covariance.iter_mut().enumerate() {
+            row[i] = if i < 3 {
+                INITIAL_ATTITUDE_STD * INITIAL_ATTITUDE_STD
+            } else {
+                bias_std * bias_std
+            };
+        }
+        Self {
+            noise,
+            gravity_tolerance: 0.1,
+            orientation: Quaternion::IDENTITY,
+            bias: Vec3::new(0.0, 0.0, 0.0),
+            covariance,
+        }
+    }
+
+    /// Starts from a known orientation instead of the identity
+    #[must_use]
+    pub fn with_orientation(mut self, orientation: Quaternion) -> Self {
+        self.orientation = orientation.normalized();
+        self
+    }
+
+    /// Starts from a known gyroscope bias in rad/s
+    #[must_use]
+    pub fn with_bias(mut self, bias: Vec3<f32>) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    /// Largest deviation from 1 g in g of accelerations used as gravity observations
+    #[must_use]
+    pub fn with_gravity_tolerance(mut self, tolerance: f32) -> Self {
+        self.gravity_tolerance = tolerance;
+        self
+    }
+
+    #[must_use]
+    pub const fn noise(&self) -> &EkfNoise {
+        &self.noise
+    }
+
+    pub fn set_noise(&mut self, noise: EkfNoise) {
+        self.noise = noise;
+    }
+
+    #[must_use]
+    pub const fn orientation(&self) -> Quaternion {
+        self.orientation
+    }
+
+    /// Estimated gyroscope bias in rad/s
+    #[must_use]
+    pub const fn bias(&self) -> Vec3<f32> {
+        self.bias
+    }
+
+    /// Error state covariance, rotation about the sensor X, Y, Z axes in rad²
+    /// followed by the gyroscope bias in (rad/s)²
+    #[must_use]
+    pub const fn covariance(&self) -> &[[f32; 6]; 6] {
+        &self.covariance
+    }
+
+    /// Standard deviation of the attitude error about the sensor axes in rad
+    #[must_use]
+    pub fn attitude_std(&self) -> Vec3<f32> {
+        let p = &self.covariance;
+        Vec3::new(
+            libm::sqrtf(p[0][0]),
+            libm::sqrtf(p[1][1]),
+            libm::sqrtf(p[2][2]),
+        )
+    }
+
+    /// Standard deviation of the gyroscope bias in rad/s
+    #[must_use]
+    pub fn bias_std(&self) -> Vec3<f32> {
+        let p = &self.covariance;
+        Vec3::new(
+            libm::sqrtf(p[3][3]),
+            libm::sqrtf(p[4][4]),
+            libm::sqrtf(p[5][5]),
+        )
+    }
+
+    /// Propagates with a gyroscope sample and corrects with the accelerometer
+    /// sample taken at the same time. Returns whether the correction was applied.
+    pub fn update(&mut self, sample: &ImuSample, dt: f32) -> bool {
+        self.predict(sample.gyro, dt);
+        self.correct(sample.accel)
+    }
+
+    /// Propagates the state by `dt` seconds with an angular rate in rad/s
+    pub fn predict(&mut self, gyro: Vec3<f32>, dt: f32) {
+        let angle = (gyro - self.bias) * dt;
+        let step = Quaternion::from_axis_angle(angle, angle.norm());
+        self.orientation = (self.orientation * step).normalized();
+
+        // The attitude error rotates backwards with the step and integrates the
+        // bias error: transition = [[step^T, -I dt], [0, I]]
+        let rotation = step.to_rotation_matrix();
+        let mut transition = [[0.0; 6]; 6];
+        for i in 0..3 {
+            for j in 0..3 {
+                transition[i][j] = rotation[j][i];
+            }
+            transition[i][i + 3] = -dt;
+            transition[i + 3][i + 3] = 1.0;
+        }
+        let p = &self.covariance;
+        let mut propagated = [[0.0; 6]; 6];
+        for (i, row) in propagated.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..6)
+                    .map(|k| {
+                        transition[i][k] * (0..6).map(|l| p[k][l] * transition[j][l]).sum::<f32>()
+                    })
+                    .sum();
+            }
+        }
+        let attitude_noise = self.noise.gyro * self.noise.gyro * dt * dt;
+        let bias_noise = self.noise.gyro_bias_walk * self.noise.gyro_bias_walk * dt;
+        for i in 0..3 {
+            propagated[i][i] += attitude_noise;
+            propagated[i + 3][i + 3] += bias_noise;
+        }
+        self.covariance = propagated;
+        self.symmetrize();
+    }
+
+    /// Corrects the state with an acceleration in g, taken as the direction of
+    /// gravity. Returns false and leaves the state untouched if the magnitude is
+    /// outside of the gravity tolerance.
+    pub fn correct(&mut self, accel: Vec3<f32>) -> bool {
+        let magnitude = accel.norm();
+        if magnitude == 0.0 || (magnitude - 1.0).abs() > self.gravity_tolerance {
+            return false;
+        }
+        let measured = accel / magnitude;
+        let g = self.orientation.rotate_inverse(Vec3::new(0.0, 0.0, 1.0));
+        let residual = measured - g;
+        // Gravity seen through a small rotation error e is g + g x e
+        let observation = [[0.0, -g.z, g.y], [g.z, 0.0, -g.x], [-g.y, g.x, 0.0]];
+        let variance = self.noise.accel * self.noise.accel;
+
+        // The three observations are independent, process them one at a time
+        // instead of inverting the innovation covariance
+        let mut error = [0.0; 6];
+        for (h, residual) in observation.iter().zip([residual.x, residual.y, residual.z]) {
+            let p = &mut self.covariance;
+            let ph: [f32; 6] = core::array::from_fn(|i| (0..3).map(|j| p[i][j] * h[j]).sum());
+            let innovation_variance = (0..3).map(|j| h[j] * ph[j]).sum::<f32>() + variance;
+            let gain = ph.map(|value| value / innovation_variance);
+            let innovation = residual - (0..3).map(|j| h[j] * error[j]).sum::<f32>();
+            for i in 0..6 {
+                error[i] += gain[i] * innovation;
+                for j in 0..6 {
+                    p[i][j] -= gain[i] * ph[j];
+                }
+            }
+        }
+        self.symmetrize();
+
+        let rotation = Vec3::new(error[0], error[1], error[2]);
+        self.orientation = (self.orientation
+            * Quaternion::from_axis_angle(rotation, rotation.norm()))
+        .normalized();
+        self.bias += Vec3::new(error[3], error[4], error[5]);
+        true
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    fn symmetrize(&mut self) {
+        let p = &mut self.covariance;
+        for i in 0..6 {
+            for j in i + 1..6 {
+                let mean = f32::midpoint(p[i][j], p[j][i]);
+                p[i][j] = mean;
+                p[j][i] = mean;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOISE: EkfNoise = EkfNoise {
+        gyro: 0.001,
+        gyro_bias_walk: 1e-4,
+        accel: 0.01,
+    };
+    const UP: Vec3<f32> = Vec3::new(0.0, 0.0, 1.0);
+
+    #[test]
+    fn converges_to_a_tilt_from_the_accelerometer() {
+        let truth = Quaternion::from_axis_angle(Vec3::new(1.0, 1.0, 0.0), 0.5);
+        let accel = truth.rotate_inverse(UP);
+        let mut ekf = AttitudeEkf::new(NOISE);
+        for _ in 0..1_000 {
+            ekf.predict(Vec3::default(), 0.01);
+            assert!(ekf.correct(accel));
+        }
+        let error = ekf.orientation().rotate_inverse(UP) - accel;
+        assert!(error.norm() < 1e-3, "{error:?}");
+    }
+
+    #[test]
+    fn estimates_roll_and_pitch_bias() {
+        let bias = Vec3::new(0.01, -0.02, 0.005);
+        let mut ekf = AttitudeEkf::new(NOISE);
+        let initial = *ekf.covariance();
+        let mut yaw_variance = initial[2][2];
+        for step in 0..6_000 {
+            ekf.predict(bias, 0.01);
+            ekf.correct(UP);
+            if step % 1_000 == 999 {
+                // Heading is not observable, its uncertainty only grows
+                assert!(ekf.covariance()[2][2] > yaw_variance);
+                yaw_variance = ekf.covariance()[2][2];
+            }
+        }
+        let error = ekf.bias() - bias;
+        assert!(error.x.abs() < 1e-3 && error.y.abs() < 1e-3, "{error:?}");
+
+        let covariance = ekf.covariance();
+        for axis in [0, 1, 3, 4] {
+            assert!(
+                covariance[axis][axis] < initial[axis][axis] / 100.0,
+                "{axis}"
+            );
+        }
+        assert!(covariance[2][2] > initial[2][2]);
+    }
+
+    #[test]
+    fn skips_correction_outside_gravity_tolerance() {
+        let mut ekf = AttitudeEkf::new(NOISE).with_gravity_tolerance(0.2);
+        ekf.predict(Vec3::new(0.1, 0.0, 0.0), 0.01);
+        let orientation = ekf.orientation();
+        let covariance = *ekf.covariance();
+
+        for accel in [Vec3::new(0.0, 0.5, 1.2), UP * 0.7, Vec3::default()] {
+            assert!(!ekf.correct(accel));
+            assert_eq!(ekf.orientation(), orientation);
+            assert_eq!(*ekf.covariance(), covariance);
+        }
+        assert!(ekf.correct(Vec3::new(0.0, 0.3, 1.0)));
+        assert_ne!(ekf.orientation(), orientation);
+    }
+}
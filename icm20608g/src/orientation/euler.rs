@@ -0,0 +1,63 @@
+// Sequence of elementary rotations. The rotations are intrinsic: `Zyx` turns
+// by yaw about Z, then by pitch about the new Y axis, then by roll about the
+// resulting X axis, which is the usual aerospace convention.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RotationOrder {
+    Xyz,
+    Xzy,
+    Yxz,
+    Yzx,
+    Zxy,
+    #[default]
+    Zyx,
+}
+impl RotationOrder {
+    // Axis indices in the order the rotations are applied
+    pub(crate) const fn axes(self) -> [usize; 3] {
+        match self {
+            Self::Xyz => [0, 1, 2],
+            Self::Xzy => [0, 2, 1],
+            Self::Yxz => [1, 0, 2],
+            Self::Yzx => [1, 2, 0],
+            Self::Zxy => [2, 0, 1],
+            Self::Zyx => [2, 1, 0],
+        }
+    }
+
+    // Whether the axes follow X -> Y -> Z -> X
+    pub(crate) const fn is_cyclic(self) -> bool {
+        matches!(self, Self::Xyz | Self::Yzx | Self::Zxy)
+    }
+}
+
+// Rotation angles in radians about the X (roll), Y (pitch) and Z (yaw) axes.
+// Only meaningful together with the `RotationOrder` they were produced with.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EulerAngles {
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+impl EulerAngles {
+    #[must_use]
+    pub const fn new(roll: f32, pitch: f32, yaw: f32) -> Self {
+        Self { roll, pitch, yaw }
+    }
+
+    pub(crate) const fn get(&self, axis: usize) -> f32 {
+        match axis {
+            0 => self.roll,
+            1 => self.pitch,
+            _ => self.yaw,
+        }
+    }
+
+    pub(crate) fn set(&mut self, axis: usize, angle: f32) {
+        match axis {
+            0 => self.roll = angle,
+            1 => self.pitch = angle,
+            _ => self.yaw = angle,
+        }
+    }
+}
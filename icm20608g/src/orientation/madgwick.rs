@@ -0,0 +1,125 @@
+use super::Quaternion;
+use crate::sample::ImuSample;
+use crate::structs::Vec3;
+
+// Madgwick's gradient descent orientation filter for accelerometer and gyroscope.
+//
+// The gyroscope is integrated and the result is pulled towards the attitude
+// that explains the measured gravity, with a step size of `beta`. Heading is
+// not observable without a magnetometer and drifts with the gyroscope.
+pub struct Madgwick {
+    // Gain of the accelerometer correction in rad/s. Larger values converge
+    // faster but let more accelerometer noise and linear acceleration through.
+    beta: f32,
+    orientation: Quaternion,
+}
+impl Madgwick {
+    #[must_use]
+    pub const fn new(beta: f32) -> Self {
+        Self {
+            beta,
+            orientation: Quaternion::IDENTITY,
+        }
+    }
+
+    /// Starts from a known orientation instead of the identity
+    #[must_use]
+    pub fn with_orientation(mut self, orientation: Quaternion) -> Self {
+        self.orientation = orientation.normalized();
+        self
+    }
+
+    #[must_use]
+    pub const fn beta(&self) -> f32 {
+        self.beta
+    }
+
+    pub fn set_beta(&mut self, beta: f32) {
+        self.beta = beta;
+    }
+
+    #[must_use]
+    pub const fn orientation(&self) -> Quaternion {
+        self.orientation
+    }
+
+    /// Advances the filter by `dt` seconds
+    pub fn update(&mut self, sample: &ImuSample, dt: f32) {
+        self.update_imu(sample.gyro, sample.accel, dt);
+    }
+
+    /// Advances the filter by `dt` seconds with the angular rate in rad/s and the
+    /// acceleration in any unit. A zero acceleration skips the correction.
+    pub fn update_imu(&mut self, gyro: Vec3<f32>, accel: Vec3<f32>, dt: f32) {
+        let q = self.orientation;
+        let rate = q * Quaternion::from(gyro);
+        let mut derivative =
+            Quaternion::new(rate.w * 0.5, rate.x * 0.5, rate.y * 0.5, rate.z * 0.5);
+
+        if let Some(accel) = accel.normalized() {
+            // Difference between gravity predicted by the orientation and measured
+            let f1 = 2.0 * (q.x * q.z - q.w * q.y) - accel.x;
+            let f2 = 2.0 * (q.w * q.x + q.y * q.z) - accel.y;
+            let f3 = 2.0 * (0.5 - q.x * q.x - q.y * q.y) - accel.z;
+            // Gradient of the squared error: Jacobian transposed times the error
+            let step = Quaternion::new(
+                -2.0 * q.y * f1 + 2.0 * q.x * f2,
+                2.0 * q.z * f1 + 2.0 * q.w * f2 - 4.0 * q.x * f3,
+                -2.0 * q.w * f1 + 2.0 * q.z * f2 - 4.0 * q.y * f3,
+                2.0 * q.x * f1 + 2.0 * q.y * f2,
+            );
+            if step.norm() > 0.0 {
+                let step = step.normalized();
+                derivative.w -= self.beta * step.w;
+                derivative.x -= self.beta * step.x;
+                derivative.y -= self.beta * step.y;
+                derivative.z -= self.beta * step.z;
+            }
+        }
+
+        self.orientation = Quaternion::new(
+            q.w + derivative.w * dt,
+            q.x + derivative.x * dt,
+            q.y + derivative.y * dt,
+            q.z + derivative.z * dt,
+        )
+        .normalized();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Earth frame up as seen by a sensor with orientation `q`
+    fn gravity(q: Quaternion) -> Vec3<f32> {
+        q.rotate_inverse(Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn converges_to_a_static_tilt() {
+        let truth = Quaternion::from_axis_angle(Vec3::new(1.0, -2.0, 0.0), 0.6);
+        let accel = gravity(truth) * 9.81;
+        let mut filter = Madgwick::new(0.2);
+        for _ in 0..2_000 {
+            filter.update_imu(Vec3::default(), accel, 0.01);
+        }
+        // Heading is not observable, compare the attitude through gravity
+        let error = gravity(filter.orientation()) - gravity(truth);
+        assert!(error.norm() < 1e-3, "{error:?}");
+    }
+
+    #[test]
+    fn integrates_gyro_without_accelerometer_weight() {
+        let axis = Vec3::new(1.0, 2.0, 2.0) / 3.0;
+        let mut filter = Madgwick::new(0.0);
+        // 0.5 rad/s for 2 s, with a level accelerometer that must be ignored
+        for _ in 0..2_000 {
+            filter.update_imu(axis * 0.5, Vec3::new(0.0, 0.0, 1.0), 0.001);
+        }
+        let expected = Quaternion::from_axis_angle(axis, 1.0);
+        let q = filter.orientation();
+        let dot = q.w * expected.w + q.x * expected.x + q.y * expected.y + q.z * expected.z;
+        assert!(dot.abs() > 1.0 - 1e-6, "{q:?}");
+    }
+}
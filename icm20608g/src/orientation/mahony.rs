@@ -0,0 +1,158 @@
+use embedded_hal::i2c::I2c;
+
+use super::Quaternion;
+use crate::calibration;
+use crate::sample::ImuSample;
+use crate::structs::{GyroOffset, Vec3};
+
+// Mahony's nonlinear complementary filter for accelerometer and gyroscope.
+//
+// The angle between measured and predicted gravity drives a PI controller on
+// the angular rate. The proportional term corrects the attitude, the integral
+// term converges to the residual gyroscope bias about the roll and pitch axes.
+// Bias about the vertical axis is not observable without a magnetometer.
+pub struct Mahony {
+    // Proportional gain in rad/s
+    kp: f32,
+    // Integral gain in rad/s², zero disables bias estimation
+    ki: f32,
+    orientation: Quaternion,
+    // Integral of the error, the negated gyroscope bias in rad/s
+    integral: Vec3<f32>,
+}
+impl Mahony {
+    #[must_use]
+    pub const fn new(kp: f32, ki: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            orientation: Quaternion::IDENTITY,
+            integral: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Starts from a known orientation instead of the identity
+    #[must_use]
+    pub fn with_orientation(mut self, orientation: Quaternion) -> Self {
+        self.orientation = orientation.normalized();
+        self
+    }
+
+    /// Starts from a known gyroscope bias in rad/s
+    #[must_use]
+    pub fn with_bias(mut self, bias: Vec3<f32>) -> Self {
+        self.integral = -bias;
+        self
+    }
+
+    pub fn set_gains(&mut self, kp: f32, ki: f32) {
+        self.kp = kp;
+        self.ki = ki;
+    }
+
+    #[must_use]
+    pub const fn orientation(&self) -> Quaternion {
+        self.orientation
+    }
+
+    /// Estimated gyroscope bias in rad/s, already removed from the rates used
+    /// by the filter
+    #[must_use]
+    pub fn bias(&self) -> Vec3<f32> {
+        -self.integral
+    }
+
+    /// Advances the filter by `dt` seconds
+    pub fn update(&mut self, sample: &ImuSample, dt: f32) {
+        self.update_imu(sample.gyro, sample.accel, dt);
+    }
+
+    /// Advances the filter by `dt` seconds with the angular rate in rad/s and the
+    /// acceleration in any unit. A zero acceleration skips the correction.
+    pub fn update_imu(&mut self, gyro: Vec3<f32>, accel: Vec3<f32>, dt: f32) {
+        let q = self.orientation;
+        let mut rate = gyro;
+
+        if let Some(accel) = accel.normalized() {
+            // Gravity direction predicted by the orientation, in the sensor frame
+            let predicted = Vec3::new(
+                2.0 * (q.x * q.z - q.w * q.y),
+                2.0 * (q.w * q.x + q.y * q.z),
+                q.w * q.w - q.x * q.x - q.y * q.y + q.z * q.z,
+            );
+            let error = accel.cross(predicted);
+            if self.ki > 0.0 {
+                self.integral += error * (self.ki * dt);
+            }
+            rate += error * self.kp + self.integral;
+        } else {
+            rate += self.integral;
+        }
+
+        let derivative = q * Quaternion::from(rate);
+        let half_dt = 0.5 * dt;
+        self.orientation = Quaternion::new(
+            q.w + derivative.w * half_dt,
+            q.x + derivative.x * half_dt,
+            q.y + derivative.y * half_dt,
+            q.z + derivative.z * half_dt,
+        )
+        .normalized();
+    }
+
+    /// Moves the estimated bias into `GyroOffset` so the device outputs bias
+    /// free rates, and clears the estimate. The offsets have a resolution of
+    /// 1/32.8 º/s, the remainder is estimated again. Returns the offsets written.
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn commit_bias<I: I2c>(&mut self, i2c: &mut I) -> Result<GyroOffset, I::Error> {
+        let offset = calibration::commit_bias(i2c, self.bias())?;
+        self.integral = Vec3::new(0.0, 0.0, 0.0);
+        Ok(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{ReadRegister, WriteRegister};
+    use crate::test_support::FakeDevice;
+
+    #[test]
+    fn recovers_roll_and_pitch_gyro_bias() {
+        let bias = Vec3::new(0.02, -0.01, 0.0);
+        let mut filter = Mahony::new(1.0, 0.1);
+        // Level and still, the gyroscope only reads its bias
+        for _ in 0..6_000 {
+            filter.update_imu(bias, Vec3::new(0.0, 0.0, 1.0), 0.01);
+        }
+        let error = filter.bias() - bias;
+        assert!(error.x.abs() < 1e-4 && error.y.abs() < 1e-4, "{error:?}");
+    }
+
+    #[test]
+    fn commit_bias_writes_offsets_and_clears_the_estimate() {
+        let mut device = FakeDevice::new();
+        GyroOffset {
+            xg_offs: 100,
+            yg_offs: -100,
+            zg_offs: 0,
+        }
+        .write(&mut device)
+        .unwrap();
+        let mut filter = Mahony::new(1.0, 0.1).with_bias(Vec3::new(
+            1.0_f32.to_radians(),
+            -0.5_f32.to_radians(),
+            0.0,
+        ));
+
+        let written = filter.commit_bias(&mut device).unwrap();
+        assert_eq!(
+            (written.xg_offs, written.yg_offs, written.zg_offs),
+            (67, -84, 0)
+        );
+        assert_eq!(GyroOffset::new(&mut device).unwrap(), written);
+        assert_eq!(filter.bias(), Vec3::new(0.0, 0.0, 0.0));
+    }
+}
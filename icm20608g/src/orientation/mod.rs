@@ -0,0 +1,10 @@
+mod ekf;
+mod euler;
+mod madgwick;
+mod mahony;
+mod quaternion;
+pub use ekf::{AttitudeEkf, EkfNoise};
+pub use euler::{EulerAngles, RotationOrder};
+pub use madgwick::Madgwick;
+pub use mahony::Mahony;
+pub use quaternion::Quaternion;
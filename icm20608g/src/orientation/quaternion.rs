@@ -0,0 +1,371 @@
+use core::f32::consts::PI;
+use core::ops::Mul;
+
+use super::{EulerAngles, RotationOrder};
+use crate::structs::Vec3;
+
+// Below this rotation angle in radians the axis of `to_axis_angle` is undefined
+const MIN_AXIS_ANGLE: f32 = 1e-6;
+
+// Unit quaternion describing the orientation of the sensor. It rotates vectors
+// from the sensor frame into the earth frame, whose Z axis points up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+impl Quaternion {
+    pub const IDENTITY: Self = Self::new(1.0, 0.0, 0.0, 0.0);
+
+    #[must_use]
+    pub const fn new(w: f32, x: f32, y: f32, z: f32) -> Self {
+        Self { w, x, y, z }
+    }
+
+    /// Rotation by `angle` radians about `axis`, which does not need to be
+    /// normalized. A zero axis gives the identity.
+    #[must_use]
+    pub fn from_axis_angle(axis: Vec3<f32>, angle: f32) -> Self {
+        let Some(axis) = axis.normalized() else {
+            return Self::IDENTITY;
+        };
+        let (sin, cos) = libm::sincosf(angle / 2.0);
+        Self::new(cos, axis.x * sin, axis.y * sin, axis.z * sin)
+    }
+
+    /// Shortest rotation that turns the direction of `from` into the direction of
+    /// `to`. Opposite directions turn by π about an axis perpendicular to `from`,
+    /// a zero vector gives the identity.
+    #[must_use]
+    pub fn from_vectors(from: Vec3<f32>, to: Vec3<f32>) -> Self {
+        let (Some(from), Some(to)) = (from.normalized(), to.normalized()) else {
+            return Self::IDENTITY;
+        };
+        let cos = from.dot(to);
+        if cos < -0.999_999 {
+            // Cross with the axis least aligned with `from`
+            let other = if from.x.abs() < 0.9 {
+                Vec3::new(1.0, 0.0, 0.0)
+            } else {
+                Vec3::new(0.0, 1.0, 0.0)
+            };
+            return Self::from_axis_angle(from.cross(other), PI);
+        }
+        let axis = from.cross(to);
+        Self::new(1.0 + cos, axis.x, axis.y, axis.z).normalized()
+    }
+
+    /// Unit rotation axis and angle in radians within [0, π]. Rotations smaller
+    /// than 1e-6 rad report the X axis.
+    #[must_use]
+    pub fn to_axis_angle(&self) -> (Vec3<f32>, f32) {
+        // Pick the representation with a non-negative scalar part, so the angle
+        // is the shortest one
+        let q = if self.w < 0.0 { -*self } else { *self };
+        let vector = Vec3::new(q.x, q.y, q.z);
+        let angle = 2.0 * libm::atan2f(vector.norm(), q.w);
+        match vector.normalized() {
+            Some(axis) if angle > MIN_AXIS_ANGLE => (axis, angle),
+            _ => (Vec3::new(1.0, 0.0, 0.0), 0.0),
+        }
+    }
+
+    /// Converts a rotation matrix, which must be orthonormal. The result has a
+    /// non-negative scalar part.
+    #[must_use]
+    pub fn from_rotation_matrix(matrix: &[[f32; 3]; 3]) -> Self {
+        let m = matrix;
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        // Shepperd's method, divide by the largest of the four components
+        let q = if trace > 0.0 {
+            let s = 2.0 * libm::sqrtf(1.0 + trace);
+            Self::new(
+                s / 4.0,
+                (m[2][1] - m[1][2]) / s,
+                (m[0][2] - m[2][0]) / s,
+                (m[1][0] - m[0][1]) / s,
+            )
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = 2.0 * libm::sqrtf(1.0 + m[0][0] - m[1][1] - m[2][2]);
+            Self::new(
+                (m[2][1] - m[1][2]) / s,
+                s / 4.0,
+                (m[0][1] + m[1][0]) / s,
+                (m[0][2] + m[2][0]) / s,
+            )
+        } else if m[1][1] > m[2][2] {
+            let s = 2.0 * libm::sqrtf(1.0 + m[1][1] - m[0][0] - m[2][2]);
+            Self::new(
+                (m[0][2] - m[2][0]) / s,
+                (m[0][1] + m[1][0]) / s,
+                s / 4.0,
+                (m[1][2] + m[2][1]) / s,
+            )
+        } else {
+            let s = 2.0 * libm::sqrtf(1.0 + m[2][2] - m[0][0] - m[1][1]);
+            Self::new(
+                (m[1][0] - m[0][1]) / s,
+                (m[0][2] + m[2][0]) / s,
+                (m[1][2] + m[2][1]) / s,
+                s / 4.0,
+            )
+        };
+        let q = q.normalized();
+        if q.w < 0.0 {
+            -q
+        } else {
+            q
+        }
+    }
+
+    /// Matrix rotating column vectors the same way as `rotate`
+    #[must_use]
+    pub fn to_rotation_matrix(&self) -> [[f32; 3]; 3] {
+        let Self { w, x, y, z } = *self;
+        [
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+            ],
+        ]
+    }
+
+    /// Composes the elementary rotations of `angles` in `order`
+    #[must_use]
+    pub fn from_euler(angles: &EulerAngles, order: RotationOrder) -> Self {
+        let [first, second, third] = order.axes();
+        let rotation = |axis: usize| {
+            let mut unit = [0.0; 3];
+            unit[axis] = 1.0;
+            Self::from_axis_angle(Vec3::new(unit[0], unit[1], unit[2]), angles.get(axis))
+        };
+        rotation(first) * rotation(second) * rotation(third)
+    }
+
+    /// Decomposes into Euler angles in `order`. The middle angle is within
+    /// [-π/2, π/2], the others within [-π, π]. In gimbal lock, where the first
+    /// and last axes line up, the last angle is reported as zero.
+    #[must_use]
+    pub fn to_euler(&self, order: RotationOrder) -> EulerAngles {
+        // Treat the middle axis as locked this close to ±90º (about 0.08º)
+        const GIMBAL_LOCK: f32 = 0.999_999;
+
+        let m = self.normalized().to_rotation_matrix();
+        let [i, j, k] = order.axes();
+        let sign = if order.is_cyclic() { 1.0 } else { -1.0 };
+        let sin_second = (sign * m[i][k]).clamp(-1.0, 1.0);
+        let second = libm::asinf(sin_second);
+        let (first, third) = if sin_second.abs() < GIMBAL_LOCK {
+            (
+                libm::atan2f(-sign * m[j][k], m[k][k]),
+                libm::atan2f(-sign * m[i][j], m[i][i]),
+            )
+        } else {
+            // With the last angle zero the middle column is the middle axis
+            // rotated by the first angle only
+            (libm::atan2f(sign * m[k][j], m[j][j]), 0.0)
+        };
+        let mut angles = EulerAngles::default();
+        angles.set(i, first);
+        angles.set(j, second);
+        angles.set(k, third);
+        angles
+    }
+
+    #[must_use]
+    pub fn norm(&self) -> f32 {
+        libm::sqrtf(self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z)
+    }
+
+    /// Scales to unit length. A zero quaternion becomes the identity.
+    #[must_use]
+    pub fn normalized(&self) -> Self {
+        let norm = self.norm();
+        if norm > 0.0 {
+            Self::new(self.w / norm, self.x / norm, self.y / norm, self.z / norm)
+        } else {
+            Self::IDENTITY
+        }
+    }
+
+    /// Inverse rotation of a unit quaternion
+    #[must_use]
+    pub const fn conjugate(&self) -> Self {
+        Self::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    /// Rotates `vector` from the sensor frame into the earth frame.
+    /// The quaternion must be normalized.
+    #[must_use]
+    pub fn rotate(&self, vector: Vec3<f32>) -> Vec3<f32> {
+        let axis = Vec3::new(self.x, self.y, self.z);
+        let t = axis.cross(vector) * 2.0;
+        vector + t * self.w + axis.cross(t)
+    }
+
+    /// Rotates `vector` from the earth frame into the sensor frame
+    #[must_use]
+    pub fn rotate_inverse(&self, vector: Vec3<f32>) -> Vec3<f32> {
+        self.conjugate().rotate(vector)
+    }
+}
+impl Mul for Quaternion {
+    type Output = Self;
+    // Hamilton product, `a * b` applies `b` first
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+            self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+        )
+    }
+}
+impl core::ops::Neg for Quaternion {
+    type Output = Self;
+    // Same rotation, opposite sign
+    fn neg(self) -> Self {
+        Self::new(-self.w, -self.x, -self.y, -self.z)
+    }
+}
+impl From<Vec3<f32>> for Quaternion {
+    // Pure quaternion with a zero scalar part
+    fn from(vector: Vec3<f32>) -> Self {
+        Self::new(0.0, vector.x, vector.y, vector.z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::FRAC_PI_2;
+
+    const ORDERS: [RotationOrder; 6] = [
+        RotationOrder::Xyz,
+        RotationOrder::Xzy,
+        RotationOrder::Yxz,
+        RotationOrder::Yzx,
+        RotationOrder::Zxy,
+        RotationOrder::Zyx,
+    ];
+
+    // q and -q are the same rotation
+    fn assert_same_rotation(a: Quaternion, b: Quaternion) {
+        let dot = a.w * b.w + a.x * b.x + a.y * b.y + a.z * b.z;
+        assert!(dot.abs() > 1.0 - 1e-6, "{a:?} vs {b:?}");
+    }
+
+    fn assert_close(a: Vec3<f32>, b: Vec3<f32>) {
+        assert!((a - b).norm() < 1e-5, "{a:?} vs {b:?}");
+    }
+
+    #[test]
+    fn euler_round_trip_in_every_order() {
+        let angles = EulerAngles::new(0.3, -0.7, 1.2);
+        for order in ORDERS {
+            let back = Quaternion::from_euler(&angles, order).to_euler(order);
+            for axis in 0..3 {
+                assert!(
+                    (back.get(axis) - angles.get(axis)).abs() < 1e-5,
+                    "{order:?}: {back:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn euler_gimbal_lock_keeps_the_rotation() {
+        for order in ORDERS {
+            let [first, middle, last] = order.axes();
+            for sign in [1.0, -1.0] {
+                let mut angles = EulerAngles::default();
+                angles.set(first, 0.4);
+                angles.set(middle, sign * FRAC_PI_2);
+                angles.set(last, 0.25);
+                let q = Quaternion::from_euler(&angles, order);
+
+                let back = q.to_euler(order);
+                assert!(back.get(last).abs() < f32::EPSILON, "{order:?}: {back:?}");
+                assert!((back.get(middle) - sign * FRAC_PI_2).abs() < 2e-3);
+                assert_same_rotation(Quaternion::from_euler(&back, order), q);
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_matrix_round_trip() {
+        // Small rotations and half turns about each axis cover all branches of
+        // `from_rotation_matrix`
+        let rotations = [
+            Quaternion::from_axis_angle(Vec3::new(1.0, 2.0, 3.0), 0.5),
+            Quaternion::from_axis_angle(Vec3::new(1.0, 0.1, -0.2), 3.0),
+            Quaternion::from_axis_angle(Vec3::new(0.1, 1.0, 0.2), 3.0),
+            Quaternion::from_axis_angle(Vec3::new(-0.2, 0.1, 1.0), 3.0),
+        ];
+        let vector = Vec3::new(0.3, -1.2, 0.7);
+        for q in rotations {
+            let matrix = q.to_rotation_matrix();
+            let back = Quaternion::from_rotation_matrix(&matrix);
+            assert!(back.w >= 0.0);
+            assert_same_rotation(back, q);
+            let [x, y, z] =
+                matrix.map(|row| row[0] * vector.x + row[1] * vector.y + row[2] * vector.z);
+            assert_close(Vec3::new(x, y, z), q.rotate(vector));
+        }
+    }
+
+    #[test]
+    fn axis_angle_round_trip() {
+        let axis = Vec3::new(2.0, -1.0, 2.0) / 3.0;
+        for angle in [0.1, 1.0, 2.5, 3.1] {
+            let (back_axis, back_angle) = Quaternion::from_axis_angle(axis, angle).to_axis_angle();
+            assert!((back_angle - angle).abs() < 1e-5, "{angle}");
+            assert_close(back_axis, axis);
+        }
+        // Negative angles turn about the opposite axis
+        let (back_axis, back_angle) = Quaternion::from_axis_angle(axis, -1.0).to_axis_angle();
+        assert!((back_angle - 1.0).abs() < 1e-5);
+        assert_close(back_axis, -axis);
+
+        let (back_axis, back_angle) = Quaternion::IDENTITY.to_axis_angle();
+        assert_eq!((back_axis, back_angle), (Vec3::new(1.0, 0.0, 0.0), 0.0));
+    }
+
+    #[test]
+    fn from_vectors_turns_opposite_vectors_half_way_round() {
+        for from in [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(0.3, -0.4, 0.5),
+        ] {
+            let q = Quaternion::from_vectors(from, -from * 3.0);
+            assert!((q.norm() - 1.0).abs() < 1e-6);
+            assert!((q.to_axis_angle().1 - PI).abs() < 1e-3);
+            assert_close(q.rotate(from), -from);
+        }
+        let q = Quaternion::from_vectors(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 1.0));
+        assert_close(
+            q.rotate(Vec3::new(0.0, 0.0, 1.0)),
+            Vec3::new(0.0, 1.0, 1.0) / libm::sqrtf(2.0),
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use embedded_hal::i2c::I2c;
+
+use crate::fifo::FifoFrame;
+use crate::structs::{
+    AccelConfig1, AccelMeasurements, GyroConfig, GyroscopeMeasurements, ReadRegister, Vec3,
+};
+
+// Orientation of the device relative to gravity, named after the sensor axis
+// pointing up (away from the ground)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Face {
+    XUp,
+    XDown,
+    YUp,
+    YDown,
+    ZUp,
+    ZDown,
+}
+impl Face {
+    pub const ALL: [Self; 6] = [
+        Self::ZUp,
+        Self::ZDown,
+        Self::XUp,
+        Self::XDown,
+        Self::YUp,
+        Self::YDown,
+    ];
+
+    /// Instruction that can be shown to the user while waiting for this face
+    #[must_use]
+    pub const fn prompt(self) -> &'static str {
+        match self {
+            Self::XUp => "Place the device with the +X axis pointing up and hold it still",
+            Self::XDown => "Place the device with the +X axis pointing down and hold it still",
+            Self::YUp => "Place the device with the +Y axis pointing up and hold it still",
+            Self::YDown => "Place the device with the +Y axis pointing down and hold it still",
+            Self::ZUp => "Place the device with the +Z axis pointing up and hold it still",
+            Self::ZDown => "Place the device with the +Z axis pointing down and hold it still",
+        }
+    }
+}
+
+// One accelerometer and gyroscope reading in physical units
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImuSample {
+    // Specific force in g
+    pub accel: Vec3<f32>,
+    // Angular rate in rad/s
+    pub gyro: Vec3<f32>,
+}
+
+// Converts raw measurements into an `ImuSample` for the configured full scales
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleScale {
+    // LSB/g
+    accel_sensitivity: f32,
+    // LSB/(rad/s)
+    gyro_sensitivity: f32,
+}
+impl SampleScale {
+    #[must_use]
+    pub fn new(accel_config: &AccelConfig1, gyro_config: &GyroConfig) -> Self {
+        Self {
+            accel_sensitivity: accel_config.sensitivity(),
+            gyro_sensitivity: gyro_config.sensitivity().to_degrees(),
+        }
+    }
+
+    /// Reads the full scales currently configured on the device
+    ///
+    /// # Errors
+    /// Will error if unable to communicate with the device
+    pub fn read<I: I2c>(i2c: &mut I) -> Result<Self, I::Error> {
+        Ok(Self::new(&AccelConfig1::new(i2c)?, &GyroConfig::new(i2c)?))
+    }
+
+    /// Acceleration in g
+    #[must_use]
+    pub fn accel(&self, accel: &AccelMeasurements) -> Vec3<f32> {
+        Vec3::new(f32::from(accel.x), f32::from(accel.y), f32::from(accel.z))
+            / self.accel_sensitivity
+    }
+
+    /// Angular rate in rad/s
+    #[must_use]
+    pub fn gyro(&self, gyro: &GyroscopeMeasurements) -> Vec3<f32> {
+        Vec3::new(f32::from(gyro.x), f32::from(gyro.y), f32::from(gyro.z)) / self.gyro_sensitivity
+    }
+
+    #[must_use]
+    pub fn sample(&self, accel: &AccelMeasurements, gyro: &GyroscopeMeasurements) -> ImuSample {
+        ImuSample {
+            accel: self.accel(accel),
+            gyro: self.gyro(gyro),
+        }
+    }
+
+    /// Converts a FIFO frame, None unless it carries both accel and gyro data
+    #[must_use]
+    pub fn frame(&self, frame: &FifoFrame) -> Option<ImuSample> {
+        Some(self.sample(frame.accel.as_ref()?, frame.gyro.as_ref()?))
+    }
+}
@@ -1,17 +1,91 @@
 #[cfg(feature = "visualize")]
 use cli_table::{print_stdout, Cell, Style, Table};
+use core::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+use embedded_hal::i2c::I2c;
 #[cfg(feature = "visualize")]
 use visualize::PrintTable;
-use embedded_hal::i2c::I2c;
 
+pub(crate) const IMU_ADDR: u8 = 0x68;
 
-const IMU_ADDR: u8 = 0x68;
-
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vec3<T> {
     pub x: T,
     pub y: T,
     pub z: T,
 }
+impl Vec3<f32> {
+    #[must_use]
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    #[must_use]
+    pub fn dot(self, other: Self) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    #[must_use]
+    pub fn cross(self, other: Self) -> Self {
+        Self {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    #[must_use]
+    pub fn norm(self) -> f32 {
+        libm::sqrtf(self.dot(self))
+    }
+
+    /// Unit vector in the same direction, None for a zero vector
+    #[must_use]
+    pub fn normalized(self) -> Option<Self> {
+        let norm = self.norm();
+        (norm > 0.0).then(|| self / norm)
+    }
+}
+impl Add for Vec3<f32> {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+impl Sub for Vec3<f32> {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+impl Neg for Vec3<f32> {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self::new(-self.x, -self.y, -self.z)
+    }
+}
+impl Mul<f32> for Vec3<f32> {
+    type Output = Self;
+    fn mul(self, scale: f32) -> Self {
+        Self::new(self.x * scale, self.y * scale, self.z * scale)
+    }
+}
+impl Div<f32> for Vec3<f32> {
+    type Output = Self;
+    fn div(self, scale: f32) -> Self {
+        Self::new(self.x / scale, self.y / scale, self.z / scale)
+    }
+}
+impl AddAssign for Vec3<f32> {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+impl SubAssign for Vec3<f32> {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
 
 pub trait WriteRegister {
     /// Will write the value from self into device register
@@ -185,6 +259,39 @@ pub struct GyroConfig {
 }
 impl GyroConfig {
     const ADDRESS: u8 = 0x1B;
+
+    /// Sensitivity in LSB/(º/s) for the selected full scale
+    #[must_use]
+    pub fn sensitivity(&self) -> f32 {
+        match self.full_scale_select & 0b11 {
+            0 => 131.0,
+            1 => 65.5,
+            2 => 32.8,
+            _ => 16.4,
+        }
+    }
+
+    /// 3 dB bandwidth in Hz of the gyroscope low pass filter selected by this
+    /// register and `config.dlpf_cfg`
+    #[must_use]
+    pub fn bandwidth_hz(&self, config: &Config) -> f32 {
+        if self.fchoice_b & 0b01 != 0 {
+            return 8_173.0;
+        }
+        if self.fchoice_b & 0b10 != 0 {
+            return 3_281.0;
+        }
+        match config.dlpf_cfg & 0b111 {
+            0 => 250.0,
+            1 => 176.0,
+            2 => 92.0,
+            3 => 41.0,
+            4 => 20.0,
+            5 => 10.0,
+            6 => 5.0,
+            _ => 3_281.0,
+        }
+    }
 }
 impl WriteRegister for GyroConfig {
     fn write<I: I2c>(&self, i2c: &mut I) -> Result<(), I::Error> {
@@ -232,6 +339,12 @@ pub struct AccelConfig1 {
 }
 impl AccelConfig1 {
     const ADDRESS: u8 = 0x1C;
+
+    /// Sensitivity in LSB/g for the selected full scale
+    #[must_use]
+    pub fn sensitivity(&self) -> f32 {
+        f32::from(16384_u16 >> (self.full_scale_select & 0b11))
+    }
 }
 impl WriteRegister for AccelConfig1 {
     fn write<I: I2c>(&self, i2c: &mut I) -> Result<(), I::Error> {
@@ -275,6 +388,23 @@ pub struct AccelConfig2 {
 }
 impl AccelConfig2 {
     const ADDRESS: u8 = 0x1D;
+
+    /// 3 dB bandwidth in Hz of the accelerometer low pass filter
+    #[must_use]
+    pub fn bandwidth_hz(&self) -> f32 {
+        if self.accel_fchoice_b {
+            return 1_046.0;
+        }
+        match self.dlpf_cfg & 0b111 {
+            0 | 1 => 218.1,
+            2 => 99.2,
+            3 => 44.8,
+            4 => 21.2,
+            5 => 10.2,
+            6 => 5.1,
+            _ => 420.0,
+        }
+    }
 }
 impl WriteRegister for AccelConfig2 {
     fn write<I: I2c>(&self, i2c: &mut I) -> Result<(), I::Error> {
@@ -302,6 +432,8 @@ impl ReadRegister for AccelConfig2 {
 }
 
 #[cfg_attr(feature = "visualize", derive(PrintTable))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GyroOffset {
     // X offset to gyro to remove DC bias. Applied before write to register.
     pub xg_offs: i16,
@@ -317,6 +449,23 @@ impl GyroOffset {
     const ADDRESS_YL: u8 = 0x16;
     const ADDRESS_ZH: u8 = 0x17;
     const ADDRESS_ZL: u8 = 0x18;
+    // LSB/(º/s), the offsets are scaled to ±1000º/s regardless of the full scale
+    const SENSITIVITY: f32 = 32.8;
+
+    /// Returns these offsets adjusted to additionally cancel `bias` in º/s
+    #[must_use]
+    pub fn compensate(&self, bias: Vec3<f32>) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        let adjust = |offset: i16, bias: f32| {
+            let adjusted = f32::from(offset) - libm::roundf(bias * Self::SENSITIVITY);
+            adjusted.clamp(f32::from(i16::MIN), f32::from(i16::MAX)) as i16
+        };
+        Self {
+            xg_offs: adjust(self.xg_offs, bias.x),
+            yg_offs: adjust(self.yg_offs, bias.y),
+            zg_offs: adjust(self.zg_offs, bias.z),
+        }
+    }
 }
 #[allow(clippy::cast_sign_loss)]
 impl WriteRegister for GyroOffset {
@@ -368,6 +517,20 @@ pub struct SampleRateDivider {
 }
 impl SampleRateDivider {
     const ADDRESS: u8 = 0x19;
+
+    /// Output data rate in Hz that results from this divider and the filter configuration.
+    /// The divider only applies while the DLPF is in use, otherwise the gyro runs at
+    /// its internal rate of 8 kHz (`dlpf_cfg` 0 or 7) or 32 kHz (`fchoice_b` set).
+    #[must_use]
+    pub fn sample_rate_hz(&self, config: &Config, gyro_config: &GyroConfig) -> f32 {
+        if gyro_config.fchoice_b != 0 {
+            32_000.0
+        } else if config.dlpf_cfg == 0 || config.dlpf_cfg == 7 {
+            8_000.0
+        } else {
+            1_000.0 / (1.0 + f32::from(self.smplrt_div))
+        }
+    }
 }
 impl WriteRegister for SampleRateDivider {
     fn write<I: I2c>(&self, i2c: &mut I) -> Result<(), I::Error> {
@@ -409,6 +572,13 @@ pub struct LowPowerModeConf {
 }
 impl LowPowerModeConf {
     const ADDRESS: u8 = 0x1E;
+
+    /// Low-power accelerometer output data rate in Hz selected by `lposc_clksel`.
+    /// Reserved values read as the highest rate.
+    #[must_use]
+    pub fn accel_rate_hz(&self) -> f32 {
+        1_000.0 / f32::from(1_u16 << (12 - self.lposc_clksel.min(11)))
+    }
 }
 impl WriteRegister for LowPowerModeConf {
     fn write<I: I2c>(&self, i2c: &mut I) -> Result<(), I::Error> {
@@ -634,6 +804,7 @@ impl ReadRegister for InterruptEnable {
 }
 
 #[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct InterruptStatus {
     // Accelerometer WoM interrupt status. Cleared on Read.
     // 111 – WoM interrupt on acceleromete.
@@ -667,6 +838,7 @@ impl ReadRegister for InterruptStatus {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct AccelMeasurements {
     pub x: i16,
     pub y: i16,
@@ -679,6 +851,17 @@ impl AccelMeasurements {
     const ADDRESS_YL: u8 = 0x3E;
     const ADDRESS_ZH: u8 = 0x3F;
     const ADDRESS_ZL: u8 = 0x40;
+
+    /// Acceleration in g for the full scale the measurement was taken at
+    #[must_use]
+    pub fn to_g(&self, config: &AccelConfig1) -> Vec3<f32> {
+        let sensitivity = config.sensitivity();
+        Vec3 {
+            x: f32::from(self.x) / sensitivity,
+            y: f32::from(self.y) / sensitivity,
+            z: f32::from(self.z) / sensitivity,
+        }
+    }
 }
 impl ReadRegister for AccelMeasurements {
     fn new<I: I2c>(i2c: &mut I) -> Result<Self, I::Error>
@@ -706,6 +889,7 @@ impl ReadRegister for AccelMeasurements {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct TemperatureMeasurements {
     // TEMP_degC = ((TEMP_OUT – RoomTemp_Offset)/Temp_Sensitivity) + 25degC
     pub temp_out: i16,
@@ -713,6 +897,16 @@ pub struct TemperatureMeasurements {
 impl TemperatureMeasurements {
     const ADDRESS_H: u8 = 0x41;
     const ADDRESS_L: u8 = 0x42;
+    // LSB/ºC
+    const SENSITIVITY: f32 = 326.8;
+    // TEMP_OUT at 25ºC
+    const ROOM_TEMP_OFFSET: f32 = 0.0;
+
+    /// Die temperature in ºC
+    #[must_use]
+    pub fn celsius(&self) -> f32 {
+        (f32::from(self.temp_out) - Self::ROOM_TEMP_OFFSET) / Self::SENSITIVITY + 25.0
+    }
 }
 impl ReadRegister for TemperatureMeasurements {
     fn new<I: I2c>(i2c: &mut I) -> Result<Self, I::Error>
@@ -730,6 +924,7 @@ impl ReadRegister for TemperatureMeasurements {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct GyroscopeMeasurements {
     // GYRO_XOUT = Gyro_Sensitivity * X_angular_rate
     // Nominal      FS_SEL = 0
@@ -751,6 +946,17 @@ impl GyroscopeMeasurements {
     const ADDRESS_YL: u8 = 0x46;
     const ADDRESS_ZH: u8 = 0x47;
     const ADDRESS_ZL: u8 = 0x48;
+
+    /// Angular rate in º/s for the full scale the measurement was taken at
+    #[must_use]
+    pub fn to_dps(&self, config: &GyroConfig) -> Vec3<f32> {
+        let sensitivity = config.sensitivity();
+        Vec3 {
+            x: f32::from(self.x) / sensitivity,
+            y: f32::from(self.y) / sensitivity,
+            z: f32::from(self.z) / sensitivity,
+        }
+    }
 }
 impl ReadRegister for GyroscopeMeasurements {
     fn new<I: I2c>(i2c: &mut I) -> Result<Self, I::Error>
@@ -916,11 +1122,14 @@ pub struct FifoReadWrite {
     pub fifo_data: Option<u8>,
 }
 impl FifoReadWrite {
-    const ADDRESS: u8 = 0x74;
+    pub(crate) const ADDRESS: u8 = 0x74;
 }
 impl WriteRegister for FifoReadWrite {
     fn write<I: I2c>(&self, i2c: &mut I) -> Result<(), I::Error> {
-        i2c.write(IMU_ADDR, &[Self::ADDRESS, self.fifo_data.unwrap_or_default()])?;
+        i2c.write(
+            IMU_ADDR,
+            &[Self::ADDRESS, self.fifo_data.unwrap_or_default()],
+        )?;
         Ok(())
     }
 }
@@ -942,7 +1151,12 @@ impl ReadRegister for FifoReadWrite {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AccelOffset {
+    // 15 bit signed offsets added to the accelerometer output in 0.98 mg steps.
+    // Holds the factory trim on power up. Bit 0 of the low registers is reserved
+    // and preserved on write.
     pub x_offs: i16,
     pub y_offs: i16,
     pub z_offs: i16,
@@ -958,12 +1172,19 @@ impl AccelOffset {
 #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
 impl WriteRegister for AccelOffset {
     fn write<I: I2c>(&self, i2c: &mut I) -> Result<(), I::Error> {
+        let mut reserved = [0];
+        i2c.write_read(IMU_ADDR, &[Self::ADDRESS_XL], &mut reserved)?;
+        let x_reserved = reserved[0] & 1;
+        i2c.write_read(IMU_ADDR, &[Self::ADDRESS_YL], &mut reserved)?;
+        let y_reserved = reserved[0] & 1;
+        i2c.write_read(IMU_ADDR, &[Self::ADDRESS_ZL], &mut reserved)?;
+        let z_reserved = reserved[0] & 1;
         let x_high = (self.x_offs >> 7) as u8;
-        let x_low = (self.x_offs << 1) as u8;
+        let x_low = (self.x_offs << 1) as u8 | x_reserved;
         let y_high = (self.y_offs >> 7) as u8;
-        let y_low = (self.y_offs << 1) as u8;
+        let y_low = (self.y_offs << 1) as u8 | y_reserved;
         let z_high = (self.z_offs >> 7) as u8;
-        let z_low = (self.z_offs << 1) as u8;
+        let z_low = (self.z_offs << 1) as u8 | z_reserved;
         i2c.write(IMU_ADDR, &[Self::ADDRESS_XH, x_high])?;
         i2c.write(IMU_ADDR, &[Self::ADDRESS_XL, x_low])?;
         i2c.write(IMU_ADDR, &[Self::ADDRESS_YH, y_high])?;
@@ -993,9 +1214,9 @@ impl ReadRegister for AccelOffset {
         let z_low = temp_buf[0];
 
         Ok(Self {
-            x_offs: (i16::from(x_high) << 7) | (i16::from(x_low) >> 1),
-            y_offs: (i16::from(y_high) << 7) | (i16::from(y_low) >> 1),
-            z_offs: (i16::from(z_high) << 7) | (i16::from(z_low) >> 1),
+            x_offs: ((i16::from(x_high) << 8) | i16::from(x_low)) >> 1,
+            y_offs: ((i16::from(y_high) << 8) | i16::from(y_low)) >> 1,
+            z_offs: ((i16::from(z_high) << 8) | i16::from(z_low)) >> 1,
         })
     }
 }
@@ -1005,6 +1226,8 @@ pub struct WhoAmI {
 }
 impl WhoAmI {
     const ADDRESS: u8 = 0x75;
+    // Value of `device_id` for the ICM-20608-G
+    pub const ICM20608G: u8 = 0xAF;
 }
 impl ReadRegister for WhoAmI {
     fn new<I: I2c>(i2c: &mut I) -> Result<Self, I::Error>
@@ -1019,3 +1242,56 @@ impl ReadRegister for WhoAmI {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeDevice;
+
+    #[test]
+    fn accel_offset_round_trips_signed_values() {
+        let mut device = FakeDevice::new();
+        for offset in [-16_384, -1, 0, 1, 1234, 16_383] {
+            let written = AccelOffset {
+                x_offs: offset,
+                y_offs: -offset / 2,
+                z_offs: offset / 3,
+            };
+            written.write(&mut device).unwrap();
+            assert_eq!(AccelOffset::new(&mut device).unwrap(), written);
+        }
+    }
+
+    #[test]
+    fn accel_offset_sign_extends_register_contents() {
+        let mut device = FakeDevice::new();
+        device.registers[usize::from(AccelOffset::ADDRESS_XH)] = 0xFF;
+        device.registers[usize::from(AccelOffset::ADDRESS_XL)] = 0xFE;
+        device.registers[usize::from(AccelOffset::ADDRESS_YH)] = 0x80;
+        device.registers[usize::from(AccelOffset::ADDRESS_YL)] = 0x01;
+        device.registers[usize::from(AccelOffset::ADDRESS_ZH)] = 0x7F;
+        device.registers[usize::from(AccelOffset::ADDRESS_ZL)] = 0xFF;
+        let offset = AccelOffset::new(&mut device).unwrap();
+        assert_eq!(offset.x_offs, -1);
+        assert_eq!(offset.y_offs, -16_384);
+        assert_eq!(offset.z_offs, 16_383);
+    }
+
+    #[test]
+    fn accel_offset_preserves_reserved_bits() {
+        let mut device = FakeDevice::new();
+        device.registers[usize::from(AccelOffset::ADDRESS_XL)] = 0x01;
+        device.registers[usize::from(AccelOffset::ADDRESS_ZL)] = 0x01;
+        AccelOffset {
+            x_offs: -2,
+            y_offs: 5,
+            z_offs: 0,
+        }
+        .write(&mut device)
+        .unwrap();
+        assert_eq!(device.registers[usize::from(AccelOffset::ADDRESS_XH)], 0xFF);
+        assert_eq!(device.registers[usize::from(AccelOffset::ADDRESS_XL)], 0xFD);
+        assert_eq!(device.registers[usize::from(AccelOffset::ADDRESS_YL)], 0x0A);
+        assert_eq!(device.registers[usize::from(AccelOffset::ADDRESS_ZL)], 0x01);
+    }
+}
@@ -0,0 +1,135 @@
+use core::convert::Infallible;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::{ErrorType, I2c, Operation, SevenBitAddress};
+
+use crate::fifo::FIFO_SIZE;
+
+const FIFO_COUNT_H: usize = 0x72;
+const FIFO_COUNT_L: usize = 0x73;
+const FIFO_R_W: usize = 0x74;
+const USER_CTRL: usize = 0x6A;
+const FIFO_RST: u8 = 1 << 2;
+
+// Register file standing in for the device. The first byte written selects a
+// register, further bytes are written and reads continue from there, with the
+// address incrementing after every byte like on the device.
+// Reads of FIFO_R_W pop bytes queued with `push_fifo` without incrementing the
+// address, the FIFO count registers follow the queue and FIFO_RST empties it.
+pub(crate) struct FakeDevice {
+    pub(crate) registers: [u8; 128],
+    pointer: usize,
+    fifo: [u8; FIFO_SIZE],
+    fifo_start: usize,
+    fifo_end: usize,
+}
+impl FakeDevice {
+    pub(crate) const fn new() -> Self {
+        Self {
+            registers: [0; 128],
+            pointer: 0,
+            fifo: [0; FIFO_SIZE],
+            fifo_start: 0,
+            fifo_end: 0,
+        }
+    }
+
+    // Appends bytes to the FIFO, anything past FIFO_SIZE is dropped
+    pub(crate) fn push_fifo(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.fifo_end < FIFO_SIZE {
+                self.fifo[self.fifo_end] = byte;
+                self.fifo_end += 1;
+            }
+        }
+        self.update_fifo_count();
+    }
+
+    fn pop_fifo(&mut self) -> u8 {
+        let byte = if self.fifo_start < self.fifo_end {
+            self.fifo_start += 1;
+            self.fifo[self.fifo_start - 1]
+        } else {
+            0
+        };
+        self.update_fifo_count();
+        byte
+    }
+
+    fn update_fifo_count(&mut self) {
+        let [high, low] = u16::try_from(self.fifo_end - self.fifo_start)
+            .unwrap_or(u16::MAX)
+            .to_be_bytes();
+        self.registers[FIFO_COUNT_H] = high;
+        self.registers[FIFO_COUNT_L] = low;
+    }
+}
+impl ErrorType for FakeDevice {
+    type Error = Infallible;
+}
+impl I2c for FakeDevice {
+    fn transaction(
+        &mut self,
+        _address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => {
+                    if let Some((&register, data)) = bytes.split_first() {
+                        self.pointer = usize::from(register);
+                        for &byte in data {
+                            self.registers[self.pointer] = byte;
+                            if self.pointer == USER_CTRL && byte & FIFO_RST != 0 {
+                                self.fifo_start = 0;
+                                self.fifo_end = 0;
+                                self.update_fifo_count();
+                            }
+                            self.pointer += 1;
+                        }
+                    }
+                }
+                Operation::Read(buffer) => {
+                    for byte in buffer.iter_mut() {
+                        if self.pointer == FIFO_R_W {
+                            *byte = self.pop_fifo();
+                        } else {
+                            *byte = self.registers[self.pointer];
+                            self.pointer += 1;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Delay that returns immediately, the fake device has no notion of time
+pub(crate) struct NoDelay;
+impl DelayNs for NoDelay {
+    fn delay_ns(&mut self, _ns: u32) {}
+}
+
+// Deterministic noise source so tests do not depend on a random number crate
+pub(crate) struct Noise(u64);
+impl Noise {
+    pub(crate) const fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    // Uniform in [-1, 1)
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn uniform(&mut self) -> f32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (self.0 >> 40) as f32 / (1_u64 << 23) as f32 - 1.0
+    }
+
+    // Roughly normal with unit standard deviation
+    pub(crate) fn normal(&mut self) -> f32 {
+        self.uniform() + self.uniform() + self.uniform()
+    }
+}